@@ -0,0 +1,353 @@
+use async_std::channel::{self, Receiver, Sender, TryRecvError, TrySendError};
+use chrono::{DateTime, Utc};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use crate::status::State;
+
+//the monitor an alert came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MonitorKind {
+    Web,
+    Database,
+    Log,
+    Tls,
+    Tcp,
+    Dns,
+    Process,
+    System,
+    Nginx,
+}
+
+impl fmt::Display for MonitorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MonitorKind::Web => write!(f, "web"),
+            MonitorKind::Database => write!(f, "db"),
+            MonitorKind::Log => write!(f, "log"),
+            MonitorKind::Tls => write!(f, "tls"),
+            MonitorKind::Tcp => write!(f, "tcp"),
+            MonitorKind::Dns => write!(f, "dns"),
+            MonitorKind::Process => write!(f, "process"),
+            MonitorKind::System => write!(f, "system"),
+            MonitorKind::Nginx => write!(f, "nginx"),
+        }
+    }
+}
+
+//how much attention an alert needs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+impl Severity {
+    //the colour notifiers mark it with
+    pub fn color(&self) -> &'static str {
+        match self {
+            Severity::Info => "#2eb886",
+            Severity::Warning => "#daa038",
+            Severity::Critical => "#d72b3f",
+        }
+    }
+}
+
+impl From<State> for Severity {
+    fn from(state: State) -> Severity {
+        match state {
+            State::Ok => Severity::Info,
+            State::Warn => Severity::Warning,
+            State::Critical => Severity::Critical,
+        }
+    }
+}
+
+//what a monitor sends to the notifiers. Monitors fill in message with the line they've always
+//sent and every notifier decides how to show the rest
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub check_id: String, //"kind/check", unique across all the monitors
+    pub monitor: MonitorKind,
+    pub check: String, //the name of the check within its monitor
+    pub severity: Severity,
+    pub state: Option<State>, //None for checks that don't have one, like matched log lines
+    //None the first time a check reports, the same as state when the status is being resent
+    pub previous_state: Option<State>,
+    pub value: Option<String>, //what was measured, when there's a single value
+    pub threshold: Option<String>, //the limits value is checked against
+    //how long the check had been in previous_state. For a reminder that's how long it's been in state
+    pub duration: Option<Duration>,
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Alert {
+    pub fn new(monitor: MonitorKind, check: &str, message: String) -> Alert {
+        Alert {
+            check_id: format!("{}/{}", monitor, check),
+            monitor,
+            check: check.to_string(),
+            severity: Severity::Info,
+            state: None,
+            previous_state: None,
+            value: None,
+            threshold: None,
+            duration: None,
+            message,
+            timestamp: Utc::now(),
+        }
+    }
+
+    //sets the state and the severity that goes with it
+    pub fn with_state(mut self, state: State, previous_state: Option<State>) -> Alert {
+        self.state = Some(state);
+        self.previous_state = previous_state;
+        self.severity = Severity::from(state);
+        self
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Alert {
+        self.severity = severity;
+        self
+    }
+
+    pub fn with_value(mut self, value: impl ToString) -> Alert {
+        self.value = Some(value.to_string());
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: impl ToString) -> Alert {
+        self.threshold = Some(threshold.to_string());
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Option<Duration>) -> Alert {
+        self.duration = duration;
+        self
+    }
+
+    //the check and its state, e.g. "disk / is CRITICAL"
+    pub fn headline(&self) -> String {
+        match self.state {
+            Some(state) => format!("{} is {}", self.check, state),
+            None => self.check.clone(),
+        }
+    }
+
+    //labels and values of what the alert knows besides its message
+    pub fn details(&self) -> Vec<(String, String)> {
+        let mut details = vec![
+            ("Monitor".to_string(), self.monitor.to_string()),
+            ("Severity".to_string(), self.severity.to_string()),
+        ];
+        if let Some(value) = &self.value {
+            details.push(("Value".to_string(), value.clone()));
+        }
+        if let Some(threshold) = &self.threshold {
+            details.push(("Threshold".to_string(), threshold.clone()));
+        }
+        if let (Some(duration), Some(previous)) = (self.duration, self.previous_state) {
+            let label = if self.is_reminder() { format!("{} for", previous) } else { format!("Was {} for", previous) };
+            details.push((label, describe_duration(duration)));
+        }
+        details
+    }
+
+    //the check was failing and is OK again
+    pub fn is_recovery(&self) -> bool {
+        self.state == Some(State::Ok) && self.previous_state.is_some_and(|p| p != State::Ok)
+    }
+
+    //nothing changed and the status is being sent again because RESEND_MINUTES went by
+    pub fn is_reminder(&self) -> bool {
+        self.state.is_some() && self.state == self.previous_state
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+//a duration the way people say it, e.g. "2h 5m" or "45s". Only the two largest units are kept
+pub fn describe_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [(secs/86400, "d"), (secs/3600%24, "h"), (secs/60%60, "m"), (secs%60, "s")];
+    let parts: Vec<String> = units.iter()
+        .skip_while(|(n, _)| *n == 0)
+        .take(2)
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+//what happens to an alert that's sent while the queue to the notifiers is full. Monitors never
+//wait for the notifiers so one of the alerts has to go
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueFull {
+    DropOldest, //make room by dropping the alert that has been waiting longest
+    DropNewest, //drop the alert being sent
+}
+
+impl FromStr for QueueFull {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<QueueFull,String> {
+        match s {
+            "drop_oldest" => Ok(QueueFull::DropOldest),
+            "drop_newest" => Ok(QueueFull::DropNewest),
+            _ => Err(format!("{} is not drop_oldest or drop_newest", s))
+        }
+    }
+}
+
+//the sending end of the queue between the monitors and the notifiers. Sending never blocks so
+//a slow notifier can't hold up a monitor. Alerts that don't fit are dropped and counted
+#[derive(Clone)]
+pub struct AlertSender {
+    tx: Sender<Alert>,
+    rx: Receiver<Alert>, //to drop the oldest alert with
+    when_full: QueueFull,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AlertSender {
+    pub fn send(&self, alert: Alert) -> Result<(),String> {
+        let mut alert = alert;
+        loop {
+            match self.tx.try_send(alert) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(_)) => return Err("the alert queue is closed".to_string()),
+                Err(TrySendError::Full(a)) if self.when_full == QueueFull::DropNewest => {
+                    self.count_drop(&a);
+                    return Ok(());
+                },
+                Err(TrySendError::Full(a)) => {
+                    //a notifier may have taken one in the meantime so there's nothing to drop
+                    if let Ok(oldest) = self.rx.try_recv() {
+                        self.count_drop(&oldest);
+                    }
+                    alert = a;
+                }
+            }
+        }
+    }
+
+    fn count_drop(&self, alert: &Alert) {
+        let total = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        log::warn!("The alert queue is full. Dropped the alert of {} ({} dropped so far)", alert.check_id, total);
+    }
+
+    //how many alerts have been dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+//the receiving end of the queue, read by the notifiers
+pub struct AlertReceiver {
+    rx: Receiver<Alert>,
+}
+
+impl AlertReceiver {
+    //waits for the next alert. None once every sender is gone
+    pub async fn recv(&self) -> Option<Alert> {
+        self.rx.recv().await.ok()
+    }
+
+    pub fn try_recv(&self) -> Result<Alert,TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    //the alerts that are queued right now
+    pub fn try_iter(&self) -> impl Iterator<Item = Alert> + '_ {
+        std::iter::from_fn(move || self.rx.try_recv().ok())
+    }
+}
+
+//makes the queue between the monitors and the notifiers. It holds up to size alerts
+pub fn alert_channel(size: usize, when_full: QueueFull) -> (AlertSender, AlertReceiver) {
+    let (tx, rx) = channel::bounded(size);
+    let sender = AlertSender {
+        tx,
+        rx: rx.clone(),
+        when_full,
+        dropped: Arc::new(AtomicU64::new(0)),
+    };
+    (sender, AlertReceiver { rx })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{alert_channel, describe_duration, Alert, MonitorKind, QueueFull, Severity};
+    use crate::status::State;
+    use std::time::Duration;
+
+    #[test]
+    fn alerts_know_what_they_are() {
+        let first = Alert::new(MonitorKind::Tcp, "redis", "[tcp] redis is CRITICAL: Could not connect.".to_string())
+            .with_state(State::Critical, None);
+        assert_eq!((first.check_id.as_str(), first.severity), ("tcp/redis", Severity::Critical));
+        assert!(!first.is_recovery() && !first.is_reminder());
+        assert_eq!(first.to_string(), "[tcp] redis is CRITICAL: Could not connect.");
+
+        let recovered = Alert::new(MonitorKind::Tcp, "redis", String::new()).with_state(State::Ok, Some(State::Warn));
+        assert!(recovered.is_recovery() && recovered.severity == Severity::Info);
+        let still = Alert::new(MonitorKind::Dns, "www", String::new()).with_state(State::Warn, Some(State::Warn));
+        assert!(still.is_reminder() && !still.is_recovery());
+
+        let line = Alert::new(MonitorKind::Log, "app", "app:ERROR".to_string()).with_severity(Severity::Warning).with_value(3);
+        assert_eq!((line.state, line.severity, line.value.as_deref()), (None, Severity::Warning, Some("3")));
+        assert!(!line.is_reminder());
+
+        assert_eq!(describe_duration(Duration::from_millis(400)), "0s");
+        assert_eq!(describe_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(describe_duration(Duration::from_secs(2*3600 + 5*60 + 7)), "2h 5m");
+        assert_eq!(describe_duration(Duration::from_secs(86400 + 30)), "1d");
+    }
+
+    #[async_std::test]
+    async fn full_queues_drop_and_count() {
+        let alert = |n: u32| Alert::new(MonitorKind::Tcp, &n.to_string(), n.to_string());
+        let (tx, rx) = alert_channel(2, QueueFull::DropOldest);
+        for n in 0..5 {
+            tx.send(alert(n)).unwrap();
+        }
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["3", "4"]);
+        assert_eq!(tx.dropped(), 3);
+
+        let (tx, rx) = alert_channel(2, QueueFull::DropNewest);
+        for n in 0..5 {
+            tx.send(alert(n)).unwrap();
+        }
+        assert_eq!(rx.recv().await.map(|a| a.message).as_deref(), Some("0"));
+        tx.send(alert(5)).unwrap();
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["1", "5"]);
+        assert_eq!(tx.dropped(), 3);
+        assert!(rx.try_recv().is_err());
+    }
+}
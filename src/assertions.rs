@@ -0,0 +1,142 @@
+use regex::Regex;
+use serde_json::Value as Json;
+use std::fmt;
+use toml::Value;
+
+//something the body of a response has to satisfy for a url to count as up
+#[derive(Clone, Debug)]
+pub enum Assertion {
+    Contains(String),
+    NotContains(String),
+    Matches(Regex),
+    JsonEquals { pointer: String, value: Json },
+    JsonAbove { pointer: String, limit: f64 },
+    JsonBelow { pointer: String, limit: f64 },
+}
+
+impl Assertion {
+    //reads one entry of an assert array. Each entry has exactly one of:
+    //{ contains = "ok" }, { not_contains = "error" }, { regex = "..." },
+    //{ json = "/status", equals = "ok" }, { json = "/queue/depth", above = 10 } or below
+    pub fn from_toml(what: &str, toml_value: &Value) -> Result<Assertion,String> {
+        let string = |key: &str| toml_value.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+        let number = |key: &str| -> Result<Option<f64>,String> {
+            match toml_value.get(key) {
+                None => Ok(None),
+                Some(Value::Integer(i)) => Ok(Some(*i as f64)),
+                Some(Value::Float(f)) => Ok(Some(*f)),
+                Some(v) => Err(format!("{} of an assertion for {} is not a number:{}", key, what, v))
+            }
+        };
+
+        if let Some(s) = string("contains") {
+            return Ok(Assertion::Contains(s));
+        }
+        if let Some(s) = string("not_contains") {
+            return Ok(Assertion::NotContains(s));
+        }
+        if let Some(r) = string("regex") {
+            let regex = Regex::new(&r).map_err(|e| format!("Invalid regex in an assertion for {}: {}", what, e))?;
+            return Ok(Assertion::Matches(regex));
+        }
+
+        let pointer = string("json")
+            .ok_or(format!("assertion for {} needs contains, not_contains, regex or json:{}", what, toml_value))?;
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            return Err(format!("json of an assertion for {} has to be a json pointer like /status:{}", what, pointer));
+        }
+        match (toml_value.get("equals"), number("above")?, number("below")?) {
+            (Some(v), None, None) => {
+                let value = serde_json::to_value(v).map_err(|e| format!("equals of an assertion for {} can't be compared to json: {}", what, e))?;
+                Ok(Assertion::JsonEquals { pointer, value })
+            },
+            (None, Some(limit), None) => Ok(Assertion::JsonAbove { pointer, limit }),
+            (None, None, Some(limit)) => Ok(Assertion::JsonBelow { pointer, limit }),
+            _ => Err(format!("json assertion for {} needs one of equals, above or below", what))
+        }
+    }
+
+    //returns what was wrong with the body if the assertion fails
+    pub fn check(&self, body: &str) -> Result<(),String> {
+        let json_value = |pointer: &str| -> Result<Json,String> {
+            let json: Json = serde_json::from_str(body).map_err(|e| format!("body is not json: {}", e))?;
+            json.pointer(pointer).cloned().ok_or(format!("{} is missing", pointer))
+        };
+        let json_number = |pointer: &str| -> Result<f64,String> {
+            let value = json_value(pointer)?;
+            value.as_f64()
+                .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+                .ok_or(format!("{} is {}, not a number", pointer, value))
+        };
+
+        match self {
+            Assertion::Contains(s) if !body.contains(s.as_str()) => Err("it's missing from the body".to_string()),
+            Assertion::NotContains(s) if body.contains(s.as_str()) => Err("it's in the body".to_string()),
+            Assertion::Matches(r) if !r.is_match(body) => Err("the body doesn't match".to_string()),
+            Assertion::JsonEquals { pointer, value } => {
+                let found = json_value(pointer)?;
+                if found == *value { Ok(()) } else { Err(format!("got {}", found)) }
+            },
+            Assertion::JsonAbove { pointer, limit } => {
+                let found = json_number(pointer)?;
+                if found > *limit { Ok(()) } else { Err(format!("got {}", found)) }
+            },
+            Assertion::JsonBelow { pointer, limit } => {
+                let found = json_number(pointer)?;
+                if found < *limit { Ok(()) } else { Err(format!("got {}", found)) }
+            },
+            _ => Ok(())
+        }
+    }
+}
+
+//names the assertion in notifications
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Assertion::Contains(s) => write!(f, "contains {:?}", s),
+            Assertion::NotContains(s) => write!(f, "not_contains {:?}", s),
+            Assertion::Matches(r) => write!(f, "regex {:?}", r.as_str()),
+            Assertion::JsonEquals { pointer, value } => write!(f, "json {} equals {}", pointer, value),
+            Assertion::JsonAbove { pointer, limit } => write!(f, "json {} above {}", pointer, limit),
+            Assertion::JsonBelow { pointer, limit } => write!(f, "json {} below {}", pointer, limit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Assertion;
+
+    fn assertion(s: &str) -> Assertion {
+        Assertion::from_toml("test", &toml::from_str(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn body_assertions() {
+        let body = r#"{"status":"degraded","queue":{"depth":12},"workers":"3"}"#;
+        assert!(assertion("contains = \"degraded\"").check(body).is_ok());
+        assert!(assertion("not_contains = \"degraded\"").check(body).is_err());
+        assert!(assertion("regex = \"depth\\\":\\\\d+\"").check(body).is_ok());
+
+        let status = assertion("json = \"/status\"\nequals = \"ok\"");
+        assert_eq!(status.to_string(), "json /status equals \"ok\"");
+        assert_eq!(status.check(body), Err("got \"degraded\"".to_string()));
+        assert!(assertion("json = \"/queue/depth\"\nequals = 12").check(body).is_ok());
+        assert!(assertion("json = \"/queue/depth\"\nbelow = 100").check(body).is_ok());
+        assert_eq!(assertion("json = \"/queue/depth\"\nabove = 20").check(body), Err("got 12".to_string()));
+        assert!(assertion("json = \"/workers\"\nabove = 2").check(body).is_ok());
+        assert_eq!(assertion("json = \"/missing\"\nabove = 2").check(body), Err("/missing is missing".to_string()));
+        assert!(assertion("json = \"/status\"\nequals = \"ok\"").check("<html>").is_err());
+    }
+
+    #[test]
+    fn bad_assertions_are_rejected() {
+        let bad = |s: &str| Assertion::from_toml("test", &toml::from_str(s).unwrap()).is_err();
+        assert!(bad("json = \"/status\""));
+        assert!(bad("json = \"status\"\nequals = 1"));
+        assert!(bad("json = \"/status\"\nabove = 1\nbelow = 2"));
+        assert!(bad("regex = \"(\""));
+        assert!(bad("starts_with = \"x\""));
+    }
+}
@@ -0,0 +1,230 @@
+use async_std::task;
+use futures::future::join_all;
+use openssl::{
+    asn1::Asn1Time,
+    ssl::{SslConnector, SslMethod, SslVerifyMode},
+    x509::X509VerifyResult,
+};
+use std::{
+        net::{TcpStream, ToSocketAddrs},
+        sync::Arc,
+        time::Duration,
+};
+use crate::alert::{Alert, AlertSender, MonitorKind};
+use crate::configuration::{CertConfig, Configuration};
+use crate::scheduler::Schedule;
+use crate::status::{ResendTimer, State, StateTracker, Transition};
+
+//what we found out about the certificates a server presented
+#[derive(Debug, PartialEq)]
+struct CertInfo {
+    days_left: f64, //until the first certificate in the chain expires. Negative once it has
+    not_after: String,
+    invalid: Option<String>, //why the chain or hostname didn't verify
+}
+
+//connects to the server and reads its certificate chain. This blocks so it's run on its own thread.
+//The handshake is allowed to finish even when verification fails so that the expiry of a bad
+//chain can still be read
+fn inspect(cert: &CertConfig) -> Result<CertInfo,String> {
+    let timeout = Duration::from_secs(cert.timeout);
+    let addr = (cert.host.as_str(), cert.port).to_socket_addrs()
+        .map_err(|e| format!("Could not resolve {}. {}", cert.host, e))?
+        .next()
+        .ok_or(format!("{} has no addresses", cert.host))?;
+    let stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+
+    let mut builder = SslConnector::builder(SslMethod::tls()).map_err(|e| e.to_string())?;
+    if let Some(ca_file) = &cert.ca_file {
+        builder.set_ca_file(ca_file).map_err(|e| format!("Could not load {}. {}", ca_file, e))?;
+    }
+    builder.set_verify_callback(SslVerifyMode::PEER, |_ok, _ctx| true);
+    let ssl = builder.build()
+        .configure()
+        .and_then(|c| c.into_ssl(&cert.server_name))
+        .map_err(|e| e.to_string())?;
+    let tls = ssl.connect(stream).map_err(|e| format!("TLS handshake failed. {}", e))?;
+
+    let chain = tls.ssl().peer_cert_chain().ok_or("the server sent no certificates")?;
+    let now = Asn1Time::days_from_now(0).map_err(|e| e.to_string())?;
+    let mut first_to_expire = None;
+    for c in chain.iter() {
+        let left = now.diff(c.not_after()).map_err(|e| e.to_string())?;
+        let days_left = left.days as f64 + left.secs as f64/86400.0;
+        if first_to_expire.as_ref().is_none_or(|(d, _)| days_left < *d) {
+            first_to_expire = Some((days_left, c.not_after().to_string()));
+        }
+    }
+    let (days_left, not_after) = first_to_expire.ok_or("the server sent no certificates")?;
+
+    let verify = tls.ssl().verify_result();
+    Ok(CertInfo {
+        days_left,
+        not_after,
+        invalid: if verify == X509VerifyResult::OK { None } else { Some(verify.error_string().to_string()) },
+    })
+}
+
+//works out the state of a certificate and a description of it
+fn evaluate(cert: &CertConfig, info: &Result<CertInfo,String>) -> (State, String) {
+    match info {
+        Err(e) => (State::Critical, format!("could not be checked. {}", e)),
+        Ok(info) => {
+            let expiry = if info.days_left < 0.0 {
+                format!("expired {} days ago on {}", (-info.days_left).ceil(), info.not_after)
+            } else {
+                format!("expires in {} days on {}", info.days_left.floor(), info.not_after)
+            };
+            match &info.invalid {
+                Some(e) => (State::Critical, format!("is invalid: {}. It {}", e, expiry)),
+                None if info.days_left <= cert.critical_days as f64 => (State::Critical, expiry),
+                None if info.days_left <= cert.warn_days as f64 => (State::Warn, expiry),
+                None => (State::Ok, expiry),
+            }
+        }
+    }
+}
+
+async fn check_cert(cert: &CertConfig, tracker: &mut StateTracker, resend: bool, alert_tx: Option<&AlertSender>) -> State {
+    let c = cert.clone();
+    let info = task::spawn_blocking(move || inspect(&c)).await;
+    let (state, description) = evaluate(cert, &info);
+    let name = cert.name();
+    match state {
+        State::Ok => log::info!("Certificate of {} {} {}", name, description, state),
+        _ => log::warn!("Certificate of {} {} {}", name, description, state),
+    }
+
+    let previous = tracker.current();
+    let duration = tracker.in_state_for();
+    let msg = match tracker.update(state) {
+        Some(Transition { from: Some(from), .. }) => format!("[tls] {} certificate {} is {} (was {})", name, description, state, from),
+        Some(_) => format!("[tls] {} certificate {} is {}", name, description, state),
+        None if resend => format!("[tls] {} certificate {} is still {}", name, description, state),
+        None => return state
+    };
+
+    if let Some(tx) = alert_tx {
+        let mut alert = Alert::new(MonitorKind::Tls, &name, msg).with_state(state, previous).with_duration(duration);
+        if let Ok(i) = &info {
+            alert = alert.with_value(i.days_left.floor()); //days until it expires
+        }
+        alert = alert.with_threshold(format!("warn_days {}, critical_days {}", cert.warn_days, cert.critical_days));
+        if let Err(e) = tx.send(alert) {
+            log::error!("Could not queue the alert:{}",e);
+        }
+    }
+    state
+}
+
+//checks one certificate on its own interval forever
+async fn watch_cert(config: &Configuration, cert: &CertConfig, alert_tx: AlertSender) {
+    let mut schedule = Schedule::every_seconds(cert.interval);
+    let mut resend_timer = ResendTimer::new(config.resend_status_minutes);
+    let mut tracker = StateTracker::default();
+    let alert_tx = if config.is_notify_configured() { Some(&alert_tx) } else { None };
+
+    loop {
+        schedule.tick().await;
+        let resend = resend_timer.due();
+        if resend {
+            log::info!("cert_mon is resending the status of {}", cert.name());
+        }
+        check_cert(cert, &mut tracker, resend, alert_tx).await;
+    }
+}
+
+pub async fn cert_mon_start(config: Arc<Configuration>, alert_tx: AlertSender) -> Result<(),String> {
+    let certificates = match &config.certificates {
+        None => return Err("TLS certificate monitoring is not configured".to_string()),
+        Some(c) => c
+    };
+
+    let watches = certificates.iter().map(|cert| watch_cert(&config, cert, alert_tx.clone()));
+    join_all(watches).await;
+    Err("cert_mon is exiting. This should never happen".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_cert;
+    use crate::alert::{alert_channel, QueueFull};
+    use crate::configuration::CertConfig;
+    use crate::status::{State, StateTracker};
+    use crate::test_support::localhost_tls;
+    use std::{io::Read, net::TcpListener, thread};
+
+    //starts a tls server for localhost with a self signed certificate that expires in days. Returns
+    //its port and the path of the certificate so it can be trusted
+    fn tls_server(days: u32) -> (u16, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (acceptor, path) = localhost_tls(days, "yam_cert_mon", port);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let acceptor = acceptor.clone();
+                thread::spawn(move || {
+                    if let Ok(mut tls) = acceptor.accept(stream.unwrap()) {
+                        let _ = tls.read(&mut [0; 16]);
+                    }
+                });
+            }
+        });
+        (port, path)
+    }
+
+    fn cert_config(port: u16, ca_file: &str) -> CertConfig {
+        let mut cert = CertConfig::new("localhost", port, 60);
+        cert.ca_file = Some(ca_file.to_string());
+        cert
+    }
+
+    #[async_std::test]
+    async fn expiry_is_checked_against_the_days() {
+        let (port, ca_file) = tls_server(10);
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut tracker = StateTracker::default();
+
+        let mut cert = cert_config(port, &ca_file);
+        assert_eq!(check_cert(&cert, &mut tracker, false, Some(&tx)).await, State::Warn);
+        cert.warn_days = 5;
+        cert.critical_days = 2;
+        assert_eq!(check_cert(&cert, &mut tracker, false, Some(&tx)).await, State::Ok);
+        assert_eq!(check_cert(&cert, &mut tracker, false, Some(&tx)).await, State::Ok);
+        cert.warn_days = 15;
+        cert.critical_days = 15;
+        assert_eq!(check_cert(&cert, &mut tracker, false, Some(&tx)).await, State::Critical);
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert_eq!(sent.len(), 3);
+        assert!(sent[0].starts_with(&format!("[tls] localhost:{} certificate expires in ", port)), "{}", sent[0]);
+        assert!(sent[0].contains(" days on ") && sent[0].ends_with(" is WARN"), "{}", sent[0]);
+        assert!(sent[1].ends_with(" is OK (was WARN)"), "{}", sent[1]);
+        assert!(sent[2].ends_with(" is CRITICAL (was OK)"), "{}", sent[2]);
+        std::fs::remove_file(&ca_file).unwrap();
+    }
+
+    #[async_std::test]
+    async fn bad_chains_and_names_are_critical() {
+        let (port, ca_file) = tls_server(100);
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+
+        let mut cert = cert_config(port, &ca_file);
+        assert_eq!(check_cert(&cert, &mut StateTracker::default(), false, Some(&tx)).await, State::Ok);
+        cert.server_name = "www.example.com".to_string();
+        assert_eq!(check_cert(&cert, &mut StateTracker::default(), false, Some(&tx)).await, State::Critical);
+        cert.server_name = "localhost".to_string();
+        cert.ca_file = None; //self signed isn't trusted by the system
+        assert_eq!(check_cert(&cert, &mut StateTracker::default(), false, Some(&tx)).await, State::Critical);
+        cert.port = 1;
+        assert_eq!(check_cert(&cert, &mut StateTracker::default(), false, Some(&tx)).await, State::Critical);
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert!(sent[1].contains("certificate is invalid: hostname mismatch"), "{}", sent[1]);
+        assert!(sent[2].contains("certificate is invalid: self-signed certificate"), "{}", sent[2]);
+        assert!(sent[3].contains("certificate could not be checked."), "{}", sent[3]);
+        std::fs::remove_file(&ca_file).unwrap();
+    }
+}
@@ -0,0 +1,523 @@
+use futures::future::{join_all, BoxFuture};
+use sqlx::{
+    mysql::{MySqlPool, MySqlPoolOptions},
+    postgres::{PgPool, PgPoolOptions},
+    sqlite::{SqlitePool, SqlitePoolOptions},
+};
+use chrono::{NaiveDateTime, Utc};
+use std::{
+        collections::HashMap,
+        time::{
+            Duration,
+            Instant,
+        },
+        sync::Arc,
+};
+use crate::alert::{Alert, AlertSender, MonitorKind};
+use crate::configuration::{Configuration, DatabaseConfig, DatabaseKind, DbQueryConfig};
+use crate::db_value::{mysql_row, pg_row, sqlite_row, DbRow, DbValue};
+use crate::rules::{History, Rule};
+use crate::scheduler::Schedule;
+use crate::status::{ResendTimer, State, StateTracker, Transition};
+
+//a database that queries can be run against. Every sql driver implements this so the
+//monitoring loop below only has to be written once
+pub trait DbBackend: Send + Sync {
+    //runs a query and returns the first row it produced
+    fn fetch_row<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<DbRow,String>>;
+}
+
+impl DbBackend for PgPool {
+    fn fetch_row<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<DbRow,String>> {
+        Box::pin(async move {
+            let row = sqlx::query(query).fetch_one(self).await.map_err(|e| e.to_string())?;
+            pg_row(&row)
+        })
+    }
+}
+
+impl DbBackend for MySqlPool {
+    fn fetch_row<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<DbRow,String>> {
+        Box::pin(async move {
+            let row = sqlx::query(query).fetch_one(self).await.map_err(|e| e.to_string())?;
+            mysql_row(&row)
+        })
+    }
+}
+
+impl DbBackend for SqlitePool {
+    fn fetch_row<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<DbRow,String>> {
+        Box::pin(async move {
+            let row = sqlx::query(query).fetch_one(self).await.map_err(|e| e.to_string())?;
+            sqlite_row(&row)
+        })
+    }
+}
+
+//creates a connection pool for whichever database the url points to
+pub async fn connect(kind: DatabaseKind, database_url: &str, pool_size: u32) -> Result<Box<dyn DbBackend>,String> {
+    let backend: Box<dyn DbBackend> = match kind {
+        DatabaseKind::Postgres => {
+            Box::new(PgPoolOptions::new().max_connections(pool_size).connect(database_url).await
+                .map_err(|e| format!("Could not create database pool:{}", e))?)
+        },
+        DatabaseKind::MySql => {
+            Box::new(MySqlPoolOptions::new().max_connections(pool_size).connect(database_url).await
+                .map_err(|e| format!("Could not create database pool:{}", e))?)
+        },
+        DatabaseKind::Sqlite => {
+            Box::new(SqlitePoolOptions::new().max_connections(pool_size).connect(database_url).await
+                .map_err(|e| format!("Could not create database pool:{}", e))?)
+        },
+    };
+    Ok(backend)
+}
+
+struct DbQuery {
+    config: DbQueryConfig,
+    last_row: Option<DbRow>, //first time around there isn't one
+    histories: HashMap<String, History>, //values of every column a rule looks at
+    state: StateTracker,
+}
+
+impl DbQuery {
+    fn new(config: DbQueryConfig) -> DbQuery {
+        DbQuery {
+            config,
+            last_row: None,
+            histories: HashMap::new(),
+            state: StateTracker::default(),
+        }
+    }
+
+    //queries with no conditions just report every change in value
+    fn has_conditions(&self) -> bool {
+        !self.config.thresholds.is_empty() || !self.config.rules.is_empty() || self.config.expect.is_some()
+    }
+
+    //the column a rule looks at. Rules default to the query's column, which defaults to the first one
+    fn rule_column<'a>(&'a self, rule: &'a Rule, row: &'a DbRow) -> Option<&'a str> {
+        let column = rule.column.as_deref().or(self.config.column.as_deref());
+        row.value(column).map(|(c, _)| c)
+    }
+
+    //works out the state of the query from the row it returned, along with the reasons for it
+    //that can't be seen from the values alone
+    fn evaluate(&mut self, row: &DbRow, now: Instant, wall_clock: NaiveDateTime) -> (State, Vec<String>) {
+        let mut state = State::Ok;
+        let mut reasons = Vec::new();
+        let column = self.config.column.as_deref();
+
+        if !self.config.thresholds.is_empty() || self.config.expect.is_some() {
+            match row.value(column) {
+                None => {
+                    state = State::Critical;
+                    reasons.push(format!("column {} is not in the result", column.unwrap_or_default()));
+                },
+                Some((name, value)) => {
+                    if let Some(expect) = &self.config.expect {
+                        if value.to_string() != *expect {
+                            state = State::Critical;
+                            reasons.push(format!("{} is {}, expected {}", name, value, expect));
+                        }
+                    }
+                    if !self.config.thresholds.is_empty() {
+                        match value.as_number(wall_clock) {
+                            Some(n) => state = state.max(self.config.thresholds.state(n)),
+                            None if *value == DbValue::Null => log::debug!("{} is NULL. Skipping its thresholds", name),
+                            None => {
+                                state = State::Critical;
+                                reasons.push(format!("{} is not a number", name));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        //record the value of every column a rule looks at. Columns shared by several rules
+        //are kept for as long as the longest of their windows
+        let mut keep: HashMap<String, Duration> = HashMap::new();
+        for rule in self.config.rules.iter() {
+            if let Some(c) = self.rule_column(rule, row) {
+                let k = keep.entry(c.to_string()).or_default();
+                *k = (*k).max(rule.window);
+            }
+        }
+        for (c, k) in keep {
+            if let Some(n) = row.get(&c).and_then(|v| v.as_number(wall_clock)) {
+                self.histories.entry(c).or_default().push(now, n, k);
+            }
+        }
+
+        for rule in self.config.rules.iter() {
+            let reason = self.rule_column(rule, row)
+                .and_then(|c| self.histories.get(c).map(|h| (c, h)))
+                .and_then(|(c, h)| rule.check(h, now).map(|r| (c, r)));
+            if let Some((c, r)) = reason {
+                state = state.max(rule.severity);
+                reasons.push(if row.columns.len() > 1 { format!("{} {}", c, r) } else { r });
+            }
+        }
+
+        (state, reasons)
+    }
+}
+
+fn send(alert_tx: Option<&AlertSender>, alert: Alert) {
+    if let Some(tx) = alert_tx {
+        if let Err(e) = tx.send(alert) {
+            log::error!("Could not queue the alert:{}",e);
+        }
+    }
+}
+
+//runs a query once. A query with conditions is sent as an alert when its state changes and one
+//without when its value changes. A query that can't be run is CRITICAL until it runs again. It's
+//also sent when resend is set and messages are tagged with the database name
+async fn check_query(db_name: &str, backend: &dyn DbBackend, db_query: &mut DbQuery, now: Instant, resend: bool, alert_tx: Option<&AlertSender>) {
+    let query_name = db_query.config.name.clone();
+    let row = match backend.fetch_row(&db_query.config.query).await {
+        Err(e) => {
+            log::warn!("Could not fetch {} from database {}: {}",query_name,db_name,e);
+            let previous = db_query.state.current();
+            let duration = db_query.state.in_state_for();
+            let msg = match db_query.state.update(State::Critical) {
+                Some(Transition { from: Some(from), .. }) => {
+                    format!("[{}] {} is {} (was {}): could not be run. {}", db_name, query_name, State::Critical, from, e)
+                },
+                Some(_) => format!("[{}] {} is {}: could not be run. {}", db_name, query_name, State::Critical, e),
+                None if resend => format!("[{}] {} is still {}: could not be run. {}", db_name, query_name, State::Critical, e),
+                None => return
+            };
+            let alert = Alert::new(MonitorKind::Database, &format!("{}/{}", db_name, query_name), msg)
+                .with_state(State::Critical, previous)
+                .with_duration(duration);
+            send(alert_tx, alert);
+            return;
+        },
+        Ok(r) => r
+    };
+
+    let (state, reasons) = db_query.evaluate(&row, now, Utc::now().naive_utc());
+    let reasons = if reasons.is_empty() { String::new() } else { format!(": {}", reasons.join(", ")) };
+    let value = match &db_query.config.message {
+        None => row.to_string(),
+        Some(template) => row.render(template),
+    };
+    log::info!("Current value in database {} for {}: {} {}{}",db_name,query_name,value,state,reasons);
+
+    let changed = db_query.last_row.as_ref() != Some(&row);
+    db_query.last_row = Some(row);
+    let previous = db_query.state.current();
+    let duration = db_query.state.in_state_for();
+    let transition = db_query.state.update(state);
+
+    //a query without conditions is only ever CRITICAL when it couldn't be run. Its recovery is
+    //reported like a change of state
+    let stateful = db_query.has_conditions() || previous == Some(State::Critical);
+    let msg = if !stateful {
+        if !changed && !resend {
+            return;
+        }
+        format!("[{}] {}:{}", db_name, query_name, value)
+    } else {
+        match transition {
+            Some(Transition { from: Some(from), .. }) => {
+                format!("[{}] {}:{} is {} (was {}){}", db_name, query_name, value, state, from, reasons)
+            },
+            Some(_) => format!("[{}] {}:{} is {}{}", db_name, query_name, value, state, reasons),
+            None if resend => format!("[{}] {}:{} is still {}{}", db_name, query_name, value, state, reasons),
+            None => return
+        }
+    };
+
+    let mut alert = Alert::new(MonitorKind::Database, &format!("{}/{}", db_name, query_name), msg).with_value(value);
+    if stateful {
+        alert = alert.with_state(state, previous).with_duration(duration);
+    }
+    if !db_query.config.thresholds.is_empty() {
+        alert = alert.with_threshold(&db_query.config.thresholds);
+    }
+    send(alert_tx, alert);
+}
+
+//runs one query on its own interval forever
+async fn watch_query(config: &Configuration, db_name: &str, backend: &dyn DbBackend, mut db_query: DbQuery, alert_tx: AlertSender) {
+    let mut schedule = Schedule::every_seconds(db_query.config.interval);
+    let mut resend_timer = ResendTimer::new(config.resend_status_minutes);
+    let alert_tx = if config.is_notify_configured() { Some(&alert_tx) } else { None };
+
+    loop {
+        schedule.tick().await;
+        let resend = resend_timer.due();
+        if resend {
+            log::info!("database_mon is resending the status of {} in {}",db_query.config.name,db_name);
+        }
+        check_query(db_name, backend, &mut db_query, Instant::now(), resend, alert_tx).await;
+    }
+}
+
+//monitors one of the configured databases. main starts one of these per database and every
+//query in it runs on its own timer
+pub async fn db_mon_start(config: Arc<Configuration>, database: DatabaseConfig, alert_tx: AlertSender) -> Result<(),String> {
+    log::info!("Monitoring {:?} database {}", database.kind, database.name);
+    let backend = connect(database.kind, &database.url, database.pool_size).await
+        .map_err(|e| format!("database {}: {}", database.name, e))?;
+
+    let watches = database.queries.iter().cloned().map(|q| {
+        watch_query(&config, &database.name, backend.as_ref(), DbQuery::new(q), alert_tx.clone())
+    });
+    join_all(watches).await;
+    Err(format!("monitoring of database {} stopped. This should never happen", database.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_query, DbBackend, DbQuery};
+    use crate::alert::{alert_channel, AlertSender, QueueFull};
+    use crate::configuration::{DatabaseKind, DbQueryConfig};
+    use crate::db_value::{DbRow, DbValue};
+    use crate::rules::{Rule, RuleKind};
+    use crate::status::{State, Thresholds};
+    use futures::future::BoxFuture;
+    use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+
+    //hands back whatever value the test last set for a query
+    struct FakeBackend {
+        values: Mutex<HashMap<String, Result<DbRow,String>>>,
+    }
+
+    impl FakeBackend {
+        fn new() -> FakeBackend {
+            FakeBackend { values: Mutex::new(HashMap::new()) }
+        }
+
+        fn set(&self, query: &str, value: Result<i64,String>) {
+            let row = value.map(|v| DbRow { columns: vec![("count".to_string(), DbValue::Int(v))] });
+            self.set_row(query, row);
+        }
+
+        fn set_row(&self, query: &str, row: Result<DbRow,String>) {
+            self.values.lock().unwrap().insert(query.to_string(), row);
+        }
+    }
+
+    impl DbBackend for FakeBackend {
+        fn fetch_row<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<DbRow,String>> {
+            let value = self.values.lock().unwrap().get(query).cloned().unwrap_or_else(|| Err("no such query".to_string()));
+            Box::pin(async move { value })
+        }
+    }
+
+    fn query_config(name: &str, thresholds: Thresholds) -> DbQueryConfig {
+        DbQueryConfig {
+            name: name.to_string(),
+            query: format!("select {}", name),
+            interval: 60,
+            thresholds,
+            rules: Vec::new(),
+            column: None,
+            expect: None,
+            message: None,
+        }
+    }
+
+    fn query(name: &str, thresholds: Thresholds) -> DbQuery {
+        DbQuery::new(query_config(name, thresholds))
+    }
+
+    //runs each query once, the way their watches would if they came due together
+    async fn run_queries(db_name: &str, backend: &dyn DbBackend, db_queries: &mut [DbQuery], now: Instant, resend: bool, alert_tx: Option<&AlertSender>) {
+        for db_query in db_queries.iter_mut() {
+            check_query(db_name, backend, db_query, now, resend, alert_tx).await;
+        }
+    }
+
+    fn queries() -> Vec<DbQuery> {
+        vec![query("users", Thresholds::default()), query("cars", Thresholds::default())]
+    }
+
+    #[async_std::test]
+    async fn only_new_or_changed_values_are_sent() {
+        let backend = FakeBackend::new();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut db_queries = queries();
+        backend.set("select users", Ok(1));
+        backend.set("select cars", Ok(7));
+
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] users:1", "[main] cars:7"]);
+
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert!(rx.try_recv().is_err());
+
+        backend.set("select users", Ok(2));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] users:2"]);
+
+        run_queries("main", &backend, &mut db_queries, Instant::now(), true, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] users:2", "[main] cars:7"]);
+    }
+
+    #[async_std::test]
+    async fn queries_that_fail_are_critical_until_they_run_again() {
+        let backend = FakeBackend::new();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut db_queries = vec![query("users", Thresholds::default()), query("queue", Thresholds { warn_above: Some(100.0), ..Thresholds::default() })];
+        backend.set("select users", Ok(1));
+        backend.set("select queue", Ok(5));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().count(), 2);
+
+        backend.set("select users", Err("connection lost".to_string()));
+        backend.set("select queue", Err("no such table: queue".to_string()));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        let sent: Vec<_> = rx.try_iter().collect();
+        assert_eq!(sent.iter().map(|a| a.message.as_str()).collect::<Vec<_>>(), vec![
+            "[main] users is CRITICAL (was OK): could not be run. connection lost",
+            "[main] queue is CRITICAL (was OK): could not be run. no such table: queue",
+        ]);
+        assert_eq!(sent[0].state, Some(State::Critical));
+        assert_eq!(sent[0].previous_state, Some(State::Ok));
+
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert!(rx.try_recv().is_err());
+        run_queries("main", &backend, &mut db_queries, Instant::now(), true, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec![
+            "[main] users is still CRITICAL: could not be run. connection lost",
+            "[main] queue is still CRITICAL: could not be run. no such table: queue",
+        ]);
+
+        backend.set("select users", Ok(1));
+        backend.set("select queue", Ok(5));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        let sent: Vec<_> = rx.try_iter().collect();
+        assert_eq!(sent.iter().map(|a| a.message.as_str()).collect::<Vec<_>>(), vec![
+            "[main] users:1 is OK (was CRITICAL)",
+            "[main] queue:5 is OK (was CRITICAL)",
+        ]);
+        assert_eq!(sent[0].state, Some(State::Ok));
+    }
+
+    #[async_std::test]
+    async fn failing_query_keeps_its_last_value() {
+        let backend = FakeBackend::new();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut db_queries = queries();
+        backend.set("select users", Ok(1));
+        backend.set("select cars", Err("connection reset".to_string()));
+
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] users:1", "[main] cars is CRITICAL: could not be run. connection reset"]);
+        assert_eq!(db_queries[1].last_row, None);
+
+        backend.set("select cars", Ok(3));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, None).await;
+        assert_eq!(db_queries[1].last_row.as_ref().map(|r| r.to_string()), Some("3".to_string()));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[async_std::test]
+    async fn sqlite_backend_fetches_values() {
+        let backend = super::connect(DatabaseKind::Sqlite, "sqlite::memory:", 1).await.unwrap();
+        let row = backend.fetch_row("select 40 + 2 as answer, 1.5 as latency, 'ok' as status, null as empty, '2020-09-01 12:00:00' as created").await.unwrap();
+        assert_eq!(row.get("answer"), Some(&DbValue::Int(42)));
+        assert_eq!(row.get("latency"), Some(&DbValue::Float(1.5)));
+        assert_eq!(row.get("status"), Some(&DbValue::Text("ok".to_string())));
+        assert_eq!(row.get("empty"), Some(&DbValue::Null));
+        assert_eq!(row.get("created").unwrap().to_string(), "2020-09-01 12:00:00");
+        assert!(matches!(row.get("created"), Some(DbValue::Timestamp(_))));
+        assert!(backend.fetch_row("select * from missing_table").await.is_err());
+    }
+
+    #[async_std::test]
+    async fn thresholds_only_send_state_changes() {
+        let backend = FakeBackend::new();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let thresholds = Thresholds { warn_above: Some(10.0), critical_above: Some(100.0), ..Thresholds::default() };
+        let mut db_queries = vec![query("queue", thresholds)];
+
+        backend.set("select queue", Ok(1));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] queue:1 is OK"]);
+
+        backend.set("select queue", Ok(5));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert!(rx.try_recv().is_err());
+
+        backend.set("select queue", Ok(500));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] queue:500 is CRITICAL (was OK)"]);
+
+        backend.set("select queue", Ok(600));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert!(rx.try_recv().is_err());
+
+        run_queries("main", &backend, &mut db_queries, Instant::now(), true, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] queue:600 is still CRITICAL"]);
+
+        backend.set("select queue", Ok(50));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] queue:50 is WARN (was CRITICAL)"]);
+    }
+
+    #[async_std::test]
+    async fn rules_raise_the_state() {
+        let backend = FakeBackend::new();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut logins = query_config("logins", Thresholds::default());
+        logins.rules.push(Rule {
+            kind: RuleKind::Delta { above: Some(500.0), below: None },
+            window: Duration::from_secs(300),
+            severity: State::Critical,
+            column: None,
+        });
+        let mut db_queries = vec![DbQuery::new(logins)];
+        let start = Instant::now();
+
+        backend.set("select logins", Ok(1000));
+        run_queries("main", &backend, &mut db_queries, start, false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] logins:1000 is OK"]);
+
+        backend.set("select logins", Ok(1700));
+        run_queries("main", &backend, &mut db_queries, start + Duration::from_secs(120), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] logins:1700 is CRITICAL (was OK): grew by 700 in 2m"]);
+
+        backend.set("select logins", Ok(1800));
+        run_queries("main", &backend, &mut db_queries, start + Duration::from_secs(600), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec!["[main] logins:1800 is OK (was CRITICAL)"]);
+    }
+
+    #[async_std::test]
+    async fn columns_can_be_checked_by_name() {
+        let backend = FakeBackend::new();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut jobs = query_config("jobs", Thresholds { warn_above: Some(100.0), ..Thresholds::default() });
+        jobs.column = Some("latency".to_string());
+        jobs.message = Some("{latency}ms, worker {status}".to_string());
+        let mut worker = query_config("worker", Thresholds::default());
+        worker.column = Some("status".to_string());
+        worker.expect = Some("running".to_string());
+        let mut db_queries = vec![DbQuery::new(jobs), DbQuery::new(worker)];
+
+        let row = |latency: f64, status: &str| Ok(DbRow { columns: vec![
+            ("count".to_string(), DbValue::Int(3)),
+            ("latency".to_string(), DbValue::Float(latency)),
+            ("status".to_string(), DbValue::Text(status.to_string())),
+        ]});
+        backend.set_row("select jobs", row(20.5, "running"));
+        backend.set_row("select worker", row(20.5, "running"));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec![
+            "[main] jobs:20.5ms, worker running is OK",
+            "[main] worker:count=3, latency=20.5, status=running is OK"]);
+
+        backend.set_row("select jobs", row(250.0, "stopped"));
+        backend.set_row("select worker", row(250.0, "stopped"));
+        run_queries("main", &backend, &mut db_queries, Instant::now(), false, Some(&tx)).await;
+        assert_eq!(rx.try_iter().map(|a| a.message).collect::<Vec<_>>(), vec![
+            "[main] jobs:250ms, worker stopped is WARN (was OK)",
+            "[main] worker:count=3, latency=250, status=stopped is CRITICAL (was OK): status is stopped, expected running"]);
+    }
+}
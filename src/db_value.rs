@@ -0,0 +1,198 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use sqlx::{
+    mysql::MySqlRow,
+    postgres::PgRow,
+    sqlite::SqliteRow,
+    types::BigDecimal,
+    Column, Row, TypeInfo, ValueRef,
+};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DbValue {
+    Null,
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bool(bool),
+    Timestamp(NaiveDateTime), //always utc
+}
+
+impl DbValue {
+    //the number thresholds and rules compare against. Timestamps are compared by their age in
+    //seconds so that "max(created_at)" can be checked for staleness
+    pub fn as_number(&self, now: NaiveDateTime) -> Option<f64> {
+        match self {
+            DbValue::Null => None,
+            DbValue::Int(i) => Some(*i as f64),
+            DbValue::Float(f) => Some(*f),
+            DbValue::Text(s) => s.trim().parse().ok(),
+            DbValue::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            DbValue::Timestamp(t) => Some((now - *t).num_milliseconds() as f64/1000.0),
+        }
+    }
+}
+
+impl fmt::Display for DbValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbValue::Null => write!(f, "NULL"),
+            DbValue::Int(i) => write!(f, "{}", i),
+            DbValue::Float(x) => write!(f, "{}", x),
+            DbValue::Text(s) => write!(f, "{}", s),
+            DbValue::Bool(b) => write!(f, "{}", b),
+            DbValue::Timestamp(t) => write!(f, "{}", t.format("%Y-%m-%d %H:%M:%S")),
+        }
+    }
+}
+
+//the first row a query returned, as (column name, value) pairs in select order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DbRow {
+    pub columns: Vec<(String, DbValue)>,
+}
+
+impl DbRow {
+    pub fn get(&self, column: &str) -> Option<&DbValue> {
+        self.columns.iter().find(|(c, _)| c == column).map(|(_, v)| v)
+    }
+
+    //the named column, or the first one when no name is given
+    pub fn value(&self, column: Option<&str>) -> Option<(&str, &DbValue)> {
+        match column {
+            None => self.columns.first(),
+            Some(name) => self.columns.iter().find(|(c, _)| c == name),
+        }.map(|(c, v)| (c.as_str(), v))
+    }
+
+    //replaces every {column} in template with the value of that column
+    pub fn render(&self, template: &str) -> String {
+        self.columns.iter().fold(template.to_string(), |s, (c, v)| {
+            s.replace(&format!("{{{}}}", c), &v.to_string())
+        })
+    }
+}
+
+impl fmt::Display for DbRow {
+    //a single column is shown as just its value, several as name=value pairs
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.columns.len() == 1 {
+            return write!(f, "{}", self.columns[0].1);
+        }
+        let pairs: Vec<String> = self.columns.iter().map(|(c, v)| format!("{}={}", c, v)).collect();
+        write!(f, "{}", pairs.join(", "))
+    }
+}
+
+//tries each rust type in turn and converts the first one the column decodes as. try_get
+//checks the sql type is compatible before decoding so the order only matters for types
+//that are compatible with several rust types
+macro_rules! decode_column {
+    ($row:expr, $i:expr, $($t:ty => $to_value:expr),+ $(,)?) => {{
+        let row = $row;
+        let i: usize = $i;
+        let is_null = row.try_get_raw(i).map(|v| v.is_null()).map_err(|e| e.to_string())?;
+        if is_null {
+            Ok(DbValue::Null)
+        }
+        $( else if let Ok(v) = row.try_get::<$t, usize>(i) {
+            Ok(($to_value)(v))
+        } )+
+        else {
+            let column = &row.columns()[i];
+            Err(format!("column {} has unsupported type {}", column.name(), column.type_info().name()))
+        }
+    }};
+}
+
+fn decimal(d: BigDecimal) -> DbValue {
+    d.to_string().parse().map(DbValue::Float).unwrap_or_else(|_| DbValue::Text(d.to_string()))
+}
+
+fn utc(t: DateTime<Utc>) -> DbValue {
+    DbValue::Timestamp(t.naive_utc())
+}
+
+fn date(d: NaiveDate) -> DbValue {
+    DbValue::Timestamp(d.and_hms_opt(0, 0, 0).unwrap_or_default())
+}
+
+fn to_row<R: Row>(row: &R, decode: impl Fn(&R, usize) -> Result<DbValue,String>) -> Result<DbRow,String> {
+    let columns = row.columns().iter().enumerate().map(|(i, c)| {
+        Ok((c.name().to_string(), decode(row, i)?))
+    }).collect::<Result<Vec<_>,String>>()?;
+    Ok(DbRow { columns })
+}
+
+pub fn pg_row(row: &PgRow) -> Result<DbRow,String> {
+    to_row(row, |row, i| decode_column!(row, i,
+        i16 => |v: i16| DbValue::Int(v as i64),
+        i32 => |v: i32| DbValue::Int(v as i64),
+        i64 => DbValue::Int,
+        f32 => |v: f32| DbValue::Float(v as f64),
+        f64 => DbValue::Float,
+        BigDecimal => decimal,
+        bool => DbValue::Bool,
+        NaiveDateTime => DbValue::Timestamp,
+        DateTime<Utc> => utc,
+        NaiveDate => date,
+        String => DbValue::Text,
+    ))
+}
+
+pub fn mysql_row(row: &MySqlRow) -> Result<DbRow,String> {
+    to_row(row, |row, i| decode_column!(row, i,
+        i8 => |v: i8| DbValue::Int(v as i64),
+        i16 => |v: i16| DbValue::Int(v as i64),
+        i32 => |v: i32| DbValue::Int(v as i64),
+        i64 => DbValue::Int,
+        u8 => |v: u8| DbValue::Int(v as i64),
+        u16 => |v: u16| DbValue::Int(v as i64),
+        u32 => |v: u32| DbValue::Int(v as i64),
+        u64 => |v: u64| DbValue::Int(v as i64),
+        f32 => |v: f32| DbValue::Float(v as f64),
+        f64 => DbValue::Float,
+        BigDecimal => decimal,
+        bool => DbValue::Bool,
+        NaiveDateTime => DbValue::Timestamp,
+        DateTime<Utc> => utc,
+        NaiveDate => date,
+        String => DbValue::Text,
+    ))
+}
+
+//sqlite types are per value rather than per column and integers are also "compatible" with
+//booleans and timestamps, so numbers are tried first and text is only read as a timestamp if
+//it parses as one
+pub fn sqlite_row(row: &SqliteRow) -> Result<DbRow,String> {
+    to_row(row, |row, i| decode_column!(row, i,
+        i64 => DbValue::Int,
+        f64 => DbValue::Float,
+        NaiveDateTime => DbValue::Timestamp,
+        String => DbValue::Text,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DbRow, DbValue};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn rows_render_and_compare() {
+        let created = NaiveDate::from_ymd_opt(2020, 9, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let row = DbRow { columns: vec![
+            ("count".to_string(), DbValue::Int(3)),
+            ("newest".to_string(), DbValue::Timestamp(created)),
+        ]};
+        assert_eq!(row.to_string(), "count=3, newest=2020-09-01 12:00:00");
+        assert_eq!(row.render("{count} jobs, newest at {newest}"), "3 jobs, newest at 2020-09-01 12:00:00");
+        assert_eq!(row.value(None), Some(("count", &DbValue::Int(3))));
+        assert_eq!(row.get("missing"), None);
+
+        let now = created + chrono::Duration::minutes(10);
+        assert_eq!(row.get("newest").unwrap().as_number(now), Some(600.0));
+        assert_eq!(DbValue::Text("ok".to_string()).as_number(now), None);
+        assert_eq!(DbValue::Null.as_number(now), None);
+    }
+}
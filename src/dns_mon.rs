@@ -0,0 +1,257 @@
+use async_std::future::timeout;
+use async_std_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    proto::rr::{RData, RecordType},
+    resolver, resolver_from_system_conf, AsyncStdResolver,
+};
+use futures::future::join_all;
+use hickory_resolver::error::ResolveErrorKind;
+use std::{
+        collections::BTreeSet,
+        net::{IpAddr, SocketAddr},
+        str::FromStr,
+        sync::Arc,
+        time::{
+            Duration,
+            Instant,
+        },
+};
+use crate::alert::{Alert, AlertSender, MonitorKind};
+use crate::configuration::{Configuration, DnsConfig};
+use crate::scheduler::Schedule;
+use crate::status::{ResendTimer, State, StateTracker, Transition};
+
+struct DnsCheck {
+    config: DnsConfig,
+    record_type: RecordType,
+    expect: Option<BTreeSet<String>>,
+    resolver: AsyncStdResolver,
+    state: StateTracker,
+}
+
+//names are compared without the trailing dot and case, addresses by their value
+fn canonical(record_type: RecordType, record: &str) -> String {
+    match record_type {
+        RecordType::A | RecordType::AAAA => IpAddr::from_str(record.trim()).map(|ip| ip.to_string()).unwrap_or_else(|_| record.to_string()),
+        RecordType::CNAME | RecordType::MX => record.trim().trim_end_matches('.').to_lowercase(),
+        _ => record.to_string(),
+    }
+}
+
+//a record as it's written in expect and shown in notifications
+fn describe(record_type: RecordType, rdata: &RData) -> String {
+    let s = match rdata {
+        RData::MX(mx) => format!("{} {}", mx.preference(), mx.exchange()),
+        RData::TXT(txt) => txt.txt_data().iter().map(|t| String::from_utf8_lossy(t).to_string()).collect(),
+        other => other.to_string(),
+    };
+    canonical(record_type, &s)
+}
+
+impl DnsCheck {
+    async fn new(config: DnsConfig) -> Result<DnsCheck,String> {
+        let record_type = RecordType::from_str(&config.record_type).map_err(|e| e.to_string())?;
+        let expect = config.expect.as_ref().map(|e| e.iter().map(|r| canonical(record_type, r)).collect());
+        let resolver = match &config.server {
+            None => resolver_from_system_conf().await
+                .map_err(|e| format!("Could not read the system resolver configuration: {}", e))?,
+            Some(server) => {
+                let server: SocketAddr = server.parse().map_err(|_| format!("Invalid name server {} for {}", server, config.name))?;
+                let servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true);
+                let mut options = ResolverOpts::default();
+                options.timeout = Duration::from_secs(config.timeout);
+                resolver(ResolverConfig::from_parts(None, Vec::new(), servers), options).await
+            }
+        };
+        Ok(DnsCheck {
+            config,
+            record_type,
+            expect,
+            resolver,
+            state: StateTracker::default(),
+        })
+    }
+
+    //looks the name up and returns the state and a description of the answer
+    async fn lookup(&self) -> (State, String) {
+        //every check has to ask the name server rather than remember the last answer
+        self.resolver.clear_cache();
+        let time_limit = Duration::from_secs(self.config.timeout);
+        let start = Instant::now();
+        let answer = match timeout(time_limit, self.resolver.lookup(self.config.domain.as_str(), self.record_type)).await {
+            Err(_) => return (State::Critical, format!("timed out after {} seconds", time_limit.as_secs())),
+            Ok(Err(e)) => return match e.kind() {
+                ResolveErrorKind::NoRecordsFound { response_code, .. } => (State::Critical, format!("has no {} records ({})", self.record_type, response_code)),
+                _ => (State::Critical, format!("could not be resolved. {}", e)),
+            },
+            Ok(Ok(lookup)) => lookup
+        };
+        let elapsed = start.elapsed().as_millis();
+
+        //the answer to an A lookup can include the CNAME records it went through
+        let records: BTreeSet<String> = answer.record_iter()
+            .filter(|r| r.record_type() == self.record_type)
+            .filter_map(|r| r.data().map(|d| describe(self.record_type, d)))
+            .collect();
+        let shown = records.iter().cloned().collect::<Vec<_>>().join(", ");
+
+        match &self.expect {
+            Some(expect) if *expect != records => {
+                let expected = expect.iter().cloned().collect::<Vec<_>>().join(", ");
+                (State::Critical, format!("answered [{}] instead of [{}] in {} ms", shown, expected, elapsed))
+            },
+            _ => (self.config.latency.state(elapsed as f64), format!("answered [{}] in {} ms", shown, elapsed)),
+        }
+    }
+}
+
+//resolves the name once and sends an alert when its state changes or it's time to resend it
+async fn check_dns(dns: &mut DnsCheck, resend: bool, alert_tx: Option<&AlertSender>) -> State {
+    let (state, description) = dns.lookup().await;
+    let name = &dns.config.name;
+    match state {
+        State::Ok => log::info!("dns check {} {} {}", name, description, state),
+        State::Warn => log::warn!("dns check {} {} {}", name, description, state),
+        State::Critical => log::error!("dns check {} {} {}", name, description, state),
+    }
+
+    let previous = dns.state.current();
+    let duration = dns.state.in_state_for();
+    let msg = match dns.state.update(state) {
+        Some(Transition { from: Some(from), .. }) => format!("[dns] {} is {} (was {}): {}", name, state, from, description),
+        Some(_) => format!("[dns] {} is {}: {}", name, state, description),
+        None if resend => format!("[dns] {} is still {}: {}", name, state, description),
+        None => return state
+    };
+
+    if let Some(tx) = alert_tx {
+        if let Err(e) = tx.send(Alert::new(MonitorKind::Dns, name, msg).with_state(state, previous).with_duration(duration)) {
+            log::error!("Could not queue the alert:{}",e);
+        }
+    }
+    state
+}
+
+//checks one name on its own interval forever
+async fn watch_dns(config: &Configuration, mut dns: DnsCheck, alert_tx: AlertSender) {
+    let mut schedule = Schedule::every_seconds(dns.config.interval);
+    let mut resend_timer = ResendTimer::new(config.resend_status_minutes);
+    let alert_tx = if config.is_notify_configured() { Some(&alert_tx) } else { None };
+
+    loop {
+        schedule.tick().await;
+        let resend = resend_timer.due();
+        if resend {
+            log::info!("dns_mon is resending the status of {}", dns.config.name);
+        }
+        check_dns(&mut dns, resend, alert_tx).await;
+    }
+}
+
+pub async fn dns_mon_start(config: Arc<Configuration>, alert_tx: AlertSender) -> Result<(),String> {
+    let dns_checks = match &config.dns_checks {
+        None => return Err("DNS monitoring is not configured".to_string()),
+        Some(d) => d
+    };
+
+    let mut watches = Vec::new();
+    for dns in dns_checks.iter() {
+        watches.push(watch_dns(&config, DnsCheck::new(dns.clone()).await?, alert_tx.clone()));
+    }
+    join_all(watches).await;
+    Err("dns_mon is exiting. This should never happen".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_dns, DnsCheck};
+    use crate::alert::{alert_channel, QueueFull};
+    use crate::configuration::DnsConfig;
+    use crate::status::{State, Thresholds};
+    use async_std_resolver::proto::{
+        op::{Message, MessageType, ResponseCode},
+        rr::{rdata::{A, MX}, Name, RData, Record, RecordType},
+    };
+    use std::{net::UdpSocket, str::FromStr, thread, time::Duration};
+
+    //a stand in name server. www.example.com has two addresses, example.com has a mail server,
+    //slow.example.com takes a while to answer and everything else doesn't exist
+    fn name_server() -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                let name = query.name().clone();
+                let mut response = Message::new();
+                response.set_id(request.id()).set_message_type(MessageType::Response).set_recursion_desired(true);
+                response.add_query(query.clone());
+                let answers: Vec<RData> = match (name.to_string().as_str(), query.query_type()) {
+                    ("www.example.com.", RecordType::A) => vec![RData::A(A::new(10, 0, 0, 2)), RData::A(A::new(10, 0, 0, 1))],
+                    ("slow.example.com.", RecordType::A) => {
+                        thread::sleep(Duration::from_millis(200));
+                        vec![RData::A(A::new(10, 0, 0, 3))]
+                    },
+                    ("example.com.", RecordType::MX) => vec![RData::MX(MX::new(10, Name::from_str("Mail.Example.com.").unwrap()))],
+                    _ => {
+                        response.set_response_code(ResponseCode::NXDomain);
+                        Vec::new()
+                    }
+                };
+                for rdata in answers {
+                    response.add_answer(Record::from_rdata(name.clone(), 60, rdata));
+                }
+                socket.send_to(&response.to_vec().unwrap(), from).unwrap();
+            }
+        });
+        addr
+    }
+
+    fn dns_config(server: &str, domain: &str, record_type: &str, expect: Option<Vec<&str>>) -> DnsConfig {
+        DnsConfig {
+            name: domain.to_string(),
+            domain: domain.to_string(),
+            record_type: record_type.to_string(),
+            expect: expect.map(|e| e.into_iter().map(|s| s.to_string()).collect()),
+            server: Some(server.to_string()),
+            latency: Thresholds::default(),
+            interval: 60,
+            timeout: 2,
+        }
+    }
+
+    #[async_std::test]
+    async fn answers_are_compared_to_the_expected_set() {
+        let server = name_server();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+
+        let mut www = DnsCheck::new(dns_config(&server, "www.example.com", "A", Some(vec!["10.0.0.1", "10.0.0.2"]))).await.unwrap();
+        assert_eq!(check_dns(&mut www, false, Some(&tx)).await, State::Ok);
+        let mut mx = DnsCheck::new(dns_config(&server, "example.com", "MX", Some(vec!["10 mail.example.com"]))).await.unwrap();
+        assert_eq!(check_dns(&mut mx, false, Some(&tx)).await, State::Ok);
+        let mut moved = DnsCheck::new(dns_config(&server, "www.example.com", "A", Some(vec!["10.0.0.1"]))).await.unwrap();
+        assert_eq!(check_dns(&mut moved, false, Some(&tx)).await, State::Critical);
+        let mut missing = DnsCheck::new(dns_config(&server, "gone.example.com", "A", None)).await.unwrap();
+        assert_eq!(check_dns(&mut missing, false, Some(&tx)).await, State::Critical);
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert!(sent[0].starts_with("[dns] www.example.com is OK: answered [10.0.0.1, 10.0.0.2] in "), "{}", sent[0]);
+        assert!(sent[1].starts_with("[dns] example.com is OK: answered [10 mail.example.com] in "), "{}", sent[1]);
+        assert!(sent[2].starts_with("[dns] www.example.com is CRITICAL: answered [10.0.0.1, 10.0.0.2] instead of [10.0.0.1] in "), "{}", sent[2]);
+        assert_eq!(sent[3], "[dns] gone.example.com is CRITICAL: has no A records (Non-Existent Domain)");
+    }
+
+    #[async_std::test]
+    async fn slow_answers_are_flagged() {
+        let server = name_server();
+        let mut config = dns_config(&server, "slow.example.com", "A", None);
+        config.latency = Thresholds { warn_above: Some(100.0), ..Thresholds::default() };
+        let mut slow = DnsCheck::new(config).await.unwrap();
+        assert_eq!(check_dns(&mut slow, false, None).await, State::Warn);
+        let mut fast = DnsCheck::new(dns_config(&server, "www.example.com", "A", None)).await.unwrap();
+        assert_eq!(check_dns(&mut fast, false, None).await, State::Ok);
+    }
+}
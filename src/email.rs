@@ -0,0 +1,598 @@
+use async_lock::Mutex;
+use async_std::task;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use openssl::{
+    base64,
+    ssl::{SslConnector, SslMethod, SslStream},
+};
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+use crate::alert::{Alert, Severity};
+use crate::configuration::{EmailConfig, SmtpSecurity};
+use crate::notifier::{hostname, Notifier};
+
+//how often a batch is tried before it's given up on
+const SEND_ATTEMPTS: u32 = 3;
+
+//talks smtp over a stream, one command and reply at a time
+struct Smtp<S> {
+    stream: S,
+    buf: Vec<u8>, //what was read past the end of the last line
+}
+
+impl<S: Read + Write> Smtp<S> {
+    fn new(stream: S) -> Smtp<S> {
+        Smtp { stream, buf: Vec::new() }
+    }
+
+    fn line(&mut self) -> Result<String,String> {
+        loop {
+            if let Some(end) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buf[..end]).to_string();
+                self.buf.drain(..end + 2);
+                return Ok(line);
+            }
+            let mut chunk = [0; 1024];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err("the server closed the connection".to_string()),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    //reads a reply, which can span several lines, and checks that its code is in the same class
+    //as expect. Returns the text of every line
+    fn reply(&mut self, expect: u16) -> Result<Vec<String>,String> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.line()?;
+            let code: u16 = line.get(..3).and_then(|c| c.parse().ok())
+                .ok_or(format!("the server sent an invalid reply: {}", line))?;
+            lines.push(line.get(4..).unwrap_or("").to_string());
+            if line.as_bytes().get(3) != Some(&b'-') {
+                if code/100 != expect/100 {
+                    return Err(format!("the server said {}", line));
+                }
+                return Ok(lines);
+            }
+        }
+    }
+
+    fn send(&mut self, line: &str) -> Result<(),String> {
+        self.stream.write_all(format!("{}\r\n", line).as_bytes()).map_err(|e| e.to_string())
+    }
+
+    fn command(&mut self, command: &str, expect: u16) -> Result<Vec<String>,String> {
+        self.send(command)?;
+        let verb = command.split([' ', ':']).next().unwrap_or(command);
+        self.reply(expect).map_err(|e| format!("{} failed: {}", verb, e))
+    }
+
+    //sends the message after DATA. Lines starting with a dot get another one so the server
+    //doesn't take them for the end of the message
+    fn data(&mut self, message: &str) -> Result<(),String> {
+        let mut data: String = message.split("\r\n")
+            .map(|l| if l.starts_with('.') { format!(".{}\r\n", l) } else { format!("{}\r\n", l) })
+            .collect();
+        data.push_str(".\r\n");
+        self.stream.write_all(data.as_bytes()).map_err(|e| e.to_string())?;
+        self.reply(250).map_err(|e| format!("the message was refused: {}", e))?;
+        Ok(())
+    }
+
+    //logs in with PLAIN when the server offers it, otherwise with LOGIN
+    fn authenticate(&mut self, extensions: &[String], username: &str, password: &str) -> Result<(),String> {
+        let mechanisms: Vec<String> = extensions.iter()
+            .filter_map(|e| e.to_uppercase().strip_prefix("AUTH ").map(|m| m.to_string()))
+            .flat_map(|m| m.split_whitespace().map(|m| m.to_string()).collect::<Vec<String>>())
+            .collect();
+        if mechanisms.iter().any(|m| m == "PLAIN") {
+            let token = base64::encode_block(format!("\0{}\0{}", username, password).as_bytes());
+            self.command(&format!("AUTH PLAIN {}", token), 235)?;
+        } else if mechanisms.iter().any(|m| m == "LOGIN") {
+            self.command("AUTH LOGIN", 334)?;
+            self.send(&base64::encode_block(username.as_bytes()))?;
+            self.reply(334).map_err(|e| format!("AUTH LOGIN failed: {}", e))?;
+            self.send(&base64::encode_block(password.as_bytes()))?;
+            self.reply(235).map_err(|e| format!("AUTH LOGIN failed: {}", e))?;
+        } else {
+            return Err("the server doesn't offer AUTH PLAIN or LOGIN".to_string());
+        }
+        Ok(())
+    }
+
+    //everything after the connection is secured: EHLO, logging in and the message itself
+    fn transaction(&mut self, config: &EmailConfig, helo: &str, message: &str) -> Result<(),String> {
+        let extensions = self.command(&format!("EHLO {}", helo), 250)?;
+        if let Some((username, password)) = &config.credentials {
+            self.authenticate(&extensions, username, password)?;
+        }
+        self.command(&format!("MAIL FROM:<{}>", config.from), 250)?;
+        for to in config.to.iter() {
+            self.command(&format!("RCPT TO:<{}>", to), 250)?;
+        }
+        self.command("DATA", 354)?;
+        self.data(message)?;
+        if let Err(e) = self.command("QUIT", 221) {
+            log::debug!("{} after the message was sent", e);
+        }
+        Ok(())
+    }
+}
+
+fn connect(config: &EmailConfig) -> Result<TcpStream,String> {
+    let timeout = Duration::from_secs(config.timeout);
+    let addrs = (config.host.as_str(), config.port).to_socket_addrs()
+        .map_err(|e| format!("Could not resolve {}. {}", config.host, e))?;
+    let mut error = format!("{} has no addresses", config.host);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+                stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+                return Ok(stream);
+            },
+            Err(e) => error = format!("Could not connect to {}. {}", addr, e),
+        }
+    }
+    Err(error)
+}
+
+fn tls(config: &EmailConfig, stream: TcpStream) -> Result<SslStream<TcpStream>,String> {
+    let mut builder = SslConnector::builder(SslMethod::tls()).map_err(|e| e.to_string())?;
+    if let Some(ca_file) = &config.ca_file {
+        builder.set_ca_file(ca_file).map_err(|e| format!("Could not load {}. {}", ca_file, e))?;
+    }
+    builder.build().connect(&config.host, stream).map_err(|e| format!("TLS handshake with {} failed. {}", config.host, e))
+}
+
+//sends one message to the recipients of config. This blocks so it's run on its own thread
+fn send_mail(config: &EmailConfig, helo: &str, message: &str) -> Result<(),String> {
+    let stream = connect(config)?;
+    match config.security {
+        SmtpSecurity::Tls => {
+            let mut smtp = Smtp::new(tls(config, stream)?);
+            smtp.reply(220)?;
+            smtp.transaction(config, helo, message)
+        },
+        SmtpSecurity::StartTls => {
+            let mut smtp = Smtp::new(stream);
+            smtp.reply(220)?;
+            let extensions = smtp.command(&format!("EHLO {}", helo), 250)?;
+            if !extensions.iter().any(|e| e.eq_ignore_ascii_case("STARTTLS")) {
+                return Err(format!("{} doesn't offer STARTTLS", config.host));
+            }
+            smtp.command("STARTTLS", 220)?;
+            let mut smtp = Smtp::new(tls(config, smtp.stream)?);
+            smtp.transaction(config, helo, message)
+        },
+        SmtpSecurity::None => {
+            let mut smtp = Smtp::new(stream);
+            smtp.reply(220)?;
+            smtp.transaction(config, helo, message)
+        },
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+//base64 in lines short enough for any mail server
+fn base64_lines(text: &str) -> String {
+    let encoded = base64::encode_block(text.as_bytes());
+    let lines: Vec<&str> = encoded.as_bytes().chunks(76)
+        .map(|l| std::str::from_utf8(l).unwrap_or(""))
+        .collect();
+    lines.join("\r\n")
+}
+
+fn subject(alerts: &[Alert]) -> String {
+    let critical = alerts.iter().filter(|a| a.severity == Severity::Critical).count();
+    match alerts {
+        [alert] => format!("[yam] {}", alert.headline()),
+        _ if critical > 0 => format!("[yam] {} alerts, {} critical", alerts.len(), critical),
+        _ => format!("[yam] {} alerts", alerts.len()),
+    }
+}
+
+fn plain_body(alerts: &[Alert], host: &str) -> String {
+    let mut body = String::new();
+    for alert in alerts {
+        body.push_str(&format!("{}\r\n", alert.message));
+        for (label, value) in alert.details() {
+            body.push_str(&format!("  {}: {}\r\n", label, value));
+        }
+        body.push_str(&format!("  Time: {}\r\n\r\n", alert.timestamp.format("%Y-%m-%d %H:%M:%S UTC")));
+    }
+    body.push_str(&format!("Sent by yam on {}\r\n", host));
+    body
+}
+
+fn html_body(alerts: &[Alert], host: &str) -> String {
+    let mut body = "<html><body style=\"font-family: sans-serif\">\r\n".to_string();
+    for alert in alerts {
+        body.push_str(&format!("<div style=\"border-left: 4px solid {}; padding-left: 8px; margin-bottom: 16px\">\r\n", alert.severity.color()));
+        body.push_str(&format!("<h3 style=\"margin: 0\">{}</h3>\r\n<p>{}</p>\r\n<table>\r\n", html_escape(&alert.headline()), html_escape(&alert.message)));
+        for (label, value) in alert.details() {
+            body.push_str(&format!("<tr><td><b>{}</b></td><td>{}</td></tr>\r\n", html_escape(&label), html_escape(&value)));
+        }
+        body.push_str(&format!("<tr><td><b>Time</b></td><td>{}</td></tr>\r\n</table>\r\n</div>\r\n", alert.timestamp.format("%Y-%m-%d %H:%M:%S UTC")));
+    }
+    body.push_str(&format!("<p style=\"color: #888888\">Sent by yam on {}</p>\r\n</body></html>\r\n", html_escape(host)));
+    body
+}
+
+//the whole email for a batch of alerts, with a plain text and an html version of it
+fn compose(config: &EmailConfig, host: &str, alerts: &[Alert], now: DateTime<Utc>) -> String {
+    let boundary = format!("yam-{:016x}", fastrand::u64(..));
+    let subject = subject(alerts);
+    let subject = if subject.is_ascii() {
+        subject
+    } else {
+        format!("=?UTF-8?B?{}?=", base64::encode_block(subject.as_bytes()))
+    };
+    let mut message = format!("From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@{}>\r\nMIME-Version: 1.0\r\n",
+        config.from, config.to.join(", "), subject, now.to_rfc2822(), boundary, host);
+    message.push_str(&format!("Content-Type: multipart/alternative; boundary=\"{}\"\r\n\r\n", boundary));
+    for (content_type, body) in [("text/plain", plain_body(alerts, host)), ("text/html", html_body(alerts, host))].iter() {
+        message.push_str(&format!("--{}\r\nContent-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+            boundary, content_type, base64_lines(body)));
+    }
+    message.push_str(&format!("--{}--\r\n", boundary));
+    message
+}
+
+#[derive(Default)]
+struct Batch {
+    alerts: Vec<Alert>,
+    since: Option<Instant>, //when the first alert of the batch came in
+    failures: u32, //how often sending the alerts in the batch failed
+}
+
+//emails alerts over smtp. Alerts that come in within the batch window of the first one are
+//sent together in one email
+pub struct EmailNotifier {
+    name: String,
+    config: EmailConfig,
+    host: String,
+    batch: Mutex<Batch>,
+}
+
+impl EmailNotifier {
+    pub fn new(name: &str, config: &EmailConfig) -> EmailNotifier {
+        EmailNotifier {
+            name: name.to_string(),
+            config: config.clone(),
+            host: hostname(),
+            batch: Mutex::new(Batch::default()),
+        }
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(),String>> {
+        Box::pin(async move {
+            let mut batch = self.batch.lock().await;
+            batch.alerts.push(alert.clone());
+            batch.since.get_or_insert_with(Instant::now);
+            Ok(())
+        })
+    }
+
+    //sends the batch once its window is over. A batch that can't be sent is kept and tried again
+    //with whatever comes in during the next window, up to SEND_ATTEMPTS times
+    fn flush(&self) -> BoxFuture<'_, Result<(),String>> {
+        Box::pin(async move {
+            let alerts = {
+                let mut batch = self.batch.lock().await;
+                if batch.since.is_none_or(|s| s.elapsed() < self.config.batch) {
+                    return Ok(());
+                }
+                batch.since = None;
+                std::mem::take(&mut batch.alerts)
+            };
+            log::debug!("Emailing {} alerts to {}", alerts.len(), self.config.to.join(", "));
+            let message = compose(&self.config, &self.host, &alerts, Utc::now());
+            let config = self.config.clone();
+            let helo = self.host.clone();
+            let result = task::spawn_blocking(move || send_mail(&config, &helo, &message)).await;
+
+            let mut batch = self.batch.lock().await;
+            let e = match result {
+                Ok(()) => {
+                    batch.failures = 0;
+                    return Ok(());
+                },
+                Err(e) => e
+            };
+            batch.failures += 1;
+            if batch.failures >= SEND_ATTEMPTS {
+                batch.failures = 0;
+                let check_ids: Vec<&str> = alerts.iter().map(|a| a.check_id.as_str()).collect();
+                return Err(format!("Gave up on emailing {} alerts after {} tries: {}. {}", alerts.len(), SEND_ATTEMPTS, check_ids.join(", "), e));
+            }
+            //put them back in front of the alerts that came in while sending
+            let newer = std::mem::replace(&mut batch.alerts, alerts);
+            batch.alerts.extend(newer);
+            batch.since = Some(Instant::now());
+            Err(format!("{} alerts could not be emailed. Trying again. {}", batch.alerts.len(), e))
+        })
+    }
+
+    fn undelivered(&self) -> BoxFuture<'_, Vec<Alert>> {
+        Box::pin(async move {
+            self.batch.lock().await.alerts.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailNotifier;
+    use crate::alert::{Alert, MonitorKind, Severity};
+    use crate::configuration::{EmailConfig, SmtpSecurity};
+    use crate::notifier::Notifier;
+    use crate::status::State;
+    use async_std::task;
+    use crate::test_support::localhost_tls;
+    use openssl::base64;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    //what the stand in server was told in one connection
+    #[derive(Default)]
+    struct Session {
+        tls: bool,
+        auth: Option<String>,
+        from: String,
+        to: Vec<String>,
+        data: String,
+    }
+
+    fn read_line<S: Read>(stream: &mut S) -> Option<String> {
+        let mut line = Vec::new();
+        let mut byte = [0; 1];
+        while !line.ends_with(b"\r\n") {
+            match stream.read(&mut byte) {
+                Ok(1) => line.push(byte[0]),
+                _ => return None,
+            }
+        }
+        Some(String::from_utf8_lossy(&line[..line.len() - 2]).to_string())
+    }
+
+    //answers commands until QUIT, or until STARTTLS when the connection has to be upgraded, in
+    //which case it returns true
+    fn serve<S: Read + Write>(stream: &mut S, session: &mut Session, offer_starttls: bool, rcpt_reply: &str) -> bool {
+        while let Some(line) = read_line(stream) {
+            let upper = line.to_uppercase();
+            let reply = if upper.starts_with("EHLO ") {
+                if offer_starttls { "250-localhost\r\n250-STARTTLS\r\n250 AUTH LOGIN PLAIN" } else { "250-localhost\r\n250 AUTH PLAIN" }
+            } else if upper == "STARTTLS" {
+                stream.write_all(b"220 go ahead\r\n").unwrap();
+                return true;
+            } else if upper.starts_with("AUTH PLAIN ") {
+                session.auth = Some(String::from_utf8(base64::decode_block(&line[11..]).unwrap()).unwrap());
+                "235 welcome"
+            } else if upper.starts_with("MAIL FROM:") {
+                session.from = line[10..].to_string();
+                "250 ok"
+            } else if upper.starts_with("RCPT TO:") {
+                session.to.push(line[8..].to_string());
+                rcpt_reply
+            } else if upper == "DATA" {
+                stream.write_all(b"354 go on\r\n").unwrap();
+                let mut data = Vec::new();
+                while let Some(line) = read_line(stream) {
+                    if line == "." {
+                        break;
+                    }
+                    data.push(line);
+                }
+                session.data = data.join("\r\n");
+                "250 queued"
+            } else if upper == "QUIT" {
+                stream.write_all(b"221 bye\r\n").unwrap();
+                return false;
+            } else {
+                "502 unknown command"
+            };
+            stream.write_all(format!("{}\r\n", reply).as_bytes()).unwrap();
+        }
+        false
+    }
+
+    //a stand in smtp server with a self signed certificate for localhost. StartTls offers STARTTLS,
+    //Tls speaks tls from the start and None never uses tls. RCPT gets the reply in rcpt_replies of
+    //the session, and the last one after that. Returns its port, the path of its certificate and
+    //what it was sent
+    fn smtp_server(security: SmtpSecurity, rcpt_replies: &'static [&'static str]) -> (u16, String, Arc<Mutex<Vec<Session>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (acceptor, path) = localhost_tls(30, "yam_email", port);
+
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+        let received = sessions.clone();
+        thread::spawn(move || {
+            for (n, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut session = Session::default();
+                let rcpt_reply = rcpt_replies[n.min(rcpt_replies.len() - 1)];
+                if security == SmtpSecurity::Tls {
+                    let mut tls = acceptor.accept(stream).unwrap();
+                    session.tls = true;
+                    tls.write_all(b"220 localhost ESMTP\r\n").unwrap();
+                    serve(&mut tls, &mut session, false, rcpt_reply);
+                } else {
+                    stream.write_all(b"220 localhost ESMTP\r\n").unwrap();
+                    if serve(&mut stream, &mut session, security == SmtpSecurity::StartTls, rcpt_reply) {
+                        let mut tls = acceptor.accept(stream).unwrap();
+                        session.tls = true;
+                        serve(&mut tls, &mut session, false, rcpt_reply);
+                    }
+                }
+                received.lock().unwrap().push(session);
+            }
+        });
+        (port, path, sessions)
+    }
+
+    fn email_config(security: SmtpSecurity, port: u16, ca_file: &str) -> EmailConfig {
+        EmailConfig {
+            host: "localhost".to_string(),
+            port,
+            security,
+            credentials: Some(("yam".to_string(), "secret".to_string())),
+            ca_file: Some(ca_file.to_string()),
+            from: "yam@example.com".to_string(),
+            to: vec!["ops@example.com".to_string(), "oncall@example.com".to_string()],
+            batch: Duration::from_millis(200),
+            timeout: 5,
+        }
+    }
+
+    //waits until the server has finished count sessions. It records them after its reply to QUIT,
+    //which the client doesn't wait for
+    async fn finished(sessions: &Arc<Mutex<Vec<Session>>>, count: usize) {
+        for _ in 0..100 {
+            if sessions.lock().unwrap().len() >= count {
+                return;
+            }
+            task::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the server saw {} sessions instead of {}", sessions.lock().unwrap().len(), count);
+    }
+
+    //the decoded body of the part of an email with content_type
+    fn part(data: &str, content_type: &str) -> String {
+        let start = data.find(&format!("Content-Type: {}", content_type)).unwrap();
+        let body = &data[start..];
+        let body = &body[body.find("\r\n\r\n").unwrap() + 4..];
+        let body: String = body[..body.find("\r\n--").unwrap()].split("\r\n").collect();
+        String::from_utf8(base64::decode_block(&body).unwrap()).unwrap()
+    }
+
+    #[async_std::test]
+    async fn alerts_are_batched_into_one_email() {
+        let (port, ca_file, sessions) = smtp_server(SmtpSecurity::StartTls, &["250 ok"]);
+        let email = EmailNotifier::new("email", &email_config(SmtpSecurity::StartTls, port, &ca_file));
+        let disk = Alert::new(MonitorKind::System, "disk /", "[system] disk / is CRITICAL (was OK): 95.0% used".to_string())
+            .with_state(State::Critical, Some(State::Ok))
+            .with_value("95.00");
+        email.notify(&disk).await.unwrap();
+        email.notify(&Alert::new(MonitorKind::Log, "app", "app:<ERROR> & more".to_string()).with_severity(Severity::Warning)).await.unwrap();
+        email.flush().await.unwrap();
+        task::sleep(Duration::from_millis(100)).await;
+        assert!(sessions.lock().unwrap().is_empty(), "the batch was sent before its window was over");
+
+        task::sleep(Duration::from_millis(150)).await;
+        email.flush().await.unwrap();
+        email.flush().await.unwrap();
+        finished(&sessions, 1).await;
+        task::sleep(Duration::from_millis(100)).await;
+        let sessions = sessions.lock().unwrap();
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert!(session.tls);
+        assert_eq!(session.auth.as_deref(), Some("\0yam\0secret"));
+        assert_eq!(session.from, "<yam@example.com>");
+        assert_eq!(session.to, vec!["<ops@example.com>", "<oncall@example.com>"]);
+        assert!(session.data.contains("Subject: [yam] 2 alerts, 1 critical\r\n"), "{}", session.data);
+        assert!(session.data.contains("To: ops@example.com, oncall@example.com\r\n"));
+        assert!(session.data.contains("Content-Type: multipart/alternative; boundary="));
+
+        let plain = part(&session.data, "text/plain");
+        assert!(plain.starts_with("[system] disk / is CRITICAL (was OK): 95.0% used\r\n  Monitor: system\r\n  Severity: critical\r\n  Value: 95.00\r\n"), "{}", plain);
+        assert!(plain.contains("app:<ERROR> & more\r\n  Monitor: log\r\n  Severity: warning\r\n"), "{}", plain);
+        let html = part(&session.data, "text/html");
+        assert!(html.contains("border-left: 4px solid #d72b3f") && html.contains("<h3 style=\"margin: 0\">disk / is CRITICAL</h3>"), "{}", html);
+        assert!(html.contains("<p>app:&lt;ERROR&gt; &amp; more</p>"), "{}", html);
+        std::fs::remove_file(&ca_file).unwrap();
+    }
+
+    #[async_std::test]
+    async fn failures_are_reported() {
+        let alert = Alert::new(MonitorKind::Tcp, "redis", "[tcp] redis is CRITICAL: Could not connect.".to_string()).with_state(State::Critical, None);
+
+        let (port, ca_file, sessions) = smtp_server(SmtpSecurity::Tls, &["250 ok"]);
+        let mut config = email_config(SmtpSecurity::Tls, port, &ca_file);
+        config.batch = Duration::from_secs(0);
+        let email = EmailNotifier::new("email", &config);
+        email.notify(&alert).await.unwrap();
+        email.flush().await.unwrap();
+        finished(&sessions, 1).await;
+        assert!(sessions.lock().unwrap()[0].data.contains("Subject: [yam] redis is CRITICAL\r\n"));
+        std::fs::remove_file(&ca_file).unwrap();
+
+        let (port, ca_file, _) = smtp_server(SmtpSecurity::StartTls, &["550 no such user"]);
+        let mut config = email_config(SmtpSecurity::StartTls, port, &ca_file);
+        config.batch = Duration::from_secs(0);
+        let email = EmailNotifier::new("email", &config);
+        email.notify(&alert).await.unwrap();
+        let error = email.flush().await.unwrap_err();
+        assert!(error.contains("RCPT failed: the server said 550 no such user"), "{}", error);
+        std::fs::remove_file(&ca_file).unwrap();
+
+        //a server that doesn't offer STARTTLS never sees the password
+        let (port, ca_file, sessions) = smtp_server(SmtpSecurity::None, &["250 ok"]);
+        let mut config = email_config(SmtpSecurity::StartTls, port, &ca_file);
+        config.batch = Duration::from_secs(0);
+        let email = EmailNotifier::new("email", &config);
+        email.notify(&alert).await.unwrap();
+        assert!(email.flush().await.unwrap_err().contains("doesn't offer STARTTLS"));
+        finished(&sessions, 1).await;
+        assert_eq!(sessions.lock().unwrap()[0].auth, None);
+        std::fs::remove_file(&ca_file).unwrap();
+    }
+
+    #[async_std::test]
+    async fn failed_batches_are_sent_again() {
+        let redis = Alert::new(MonitorKind::Tcp, "redis", "[tcp] redis is CRITICAL: Could not connect.".to_string()).with_state(State::Critical, None);
+        let postgres = Alert::new(MonitorKind::Tcp, "postgres", "[tcp] postgres is CRITICAL: Could not connect.".to_string()).with_state(State::Critical, None);
+
+        let (port, ca_file, sessions) = smtp_server(SmtpSecurity::StartTls, &["451 try again later", "250 ok"]);
+        let mut config = email_config(SmtpSecurity::StartTls, port, &ca_file);
+        config.batch = Duration::from_secs(0);
+        let email = EmailNotifier::new("email", &config);
+        email.notify(&redis).await.unwrap();
+        assert!(email.flush().await.unwrap_err().contains("451 try again later"));
+        assert_eq!(email.undelivered().await.len(), 1);
+
+        email.notify(&postgres).await.unwrap();
+        email.flush().await.unwrap();
+        finished(&sessions, 2).await;
+        let plain = part(&sessions.lock().unwrap()[1].data, "text/plain");
+        assert!(plain.contains("[tcp] redis is CRITICAL") && plain.contains("[tcp] postgres is CRITICAL"), "{}", plain);
+        assert!(email.undelivered().await.is_empty());
+        std::fs::remove_file(&ca_file).unwrap();
+
+        //and given up on after a few tries
+        let (port, ca_file, _) = smtp_server(SmtpSecurity::StartTls, &["550 no such user"]);
+        let mut config = email_config(SmtpSecurity::StartTls, port, &ca_file);
+        config.batch = Duration::from_secs(0);
+        let email = EmailNotifier::new("email", &config);
+        email.notify(&redis).await.unwrap();
+        for _ in 1..super::SEND_ATTEMPTS {
+            assert!(email.flush().await.unwrap_err().contains("Trying again"));
+        }
+        let error = email.flush().await.unwrap_err();
+        assert!(error.contains("Gave up on emailing 1 alerts after 3 tries: tcp/redis"), "{}", error);
+        assert!(email.undelivered().await.is_empty());
+        std::fs::remove_file(&ca_file).unwrap();
+    }
+}
@@ -0,0 +1,19 @@
+use futures::future::BoxFuture;
+use surf::{
+    http::header::{HeaderName, HeaderValue},
+    middleware::{HttpClient, Middleware, Next, Request, Response},
+    Exception,
+};
+
+//adds headers that are only known at runtime to a request. surf only takes header names known at
+//compile time so they're set on the underlying request instead
+pub struct ExtraHeaders(pub Vec<(HeaderName, HeaderValue)>);
+
+impl<C: HttpClient> Middleware<C> for ExtraHeaders {
+    fn handle<'a>(&'a self, mut req: Request, client: C, next: Next<'a, C>) -> BoxFuture<'a, Result<Response, Exception>> {
+        for (name, value) in self.0.iter() {
+            req.headers_mut().insert(name.clone(), value.clone());
+        }
+        next.run(req, client)
+    }
+}
@@ -1,6 +1,23 @@
 pub mod configuration;
+pub mod status;
+pub mod alert;
+pub mod rules;
+pub mod scheduler;
+pub mod headers;
+pub mod notifier;
 pub mod slack;
+pub mod email;
+pub mod webhook;
+pub mod assertions;
 pub mod web_mon;
-pub mod postgres_mon;
-pub mod mysql_mon;
-//pub mod log_mon;
+pub mod db_value;
+pub mod db_mon;
+pub mod log_mon;
+pub mod cert_mon;
+pub mod tcp_mon;
+pub mod dns_mon;
+pub mod process_mon;
+pub mod system_mon;
+pub mod nginx;
+#[cfg(test)]
+pub mod test_support;
@@ -0,0 +1,274 @@
+use async_std::{
+    fs::File,
+    io::{
+        prelude::{ReadExt, SeekExt},
+        SeekFrom,
+    },
+};
+use futures::future::join_all;
+use regex::Regex;
+use std::{
+        os::unix::fs::MetadataExt,
+        sync::Arc,
+};
+use crate::alert::{Alert, AlertSender, MonitorKind, Severity};
+use crate::configuration::Configuration;
+use crate::scheduler::Schedule;
+
+//how much of the start of a file is kept to notice it was truncated and rewritten
+const HEAD_BYTES: usize = 64;
+//the longest line that's kept whole. A longer one is checked in pieces of this size so a file
+//without newlines can't use up the memory
+const MAX_LINE_BYTES: usize = 64*1024;
+
+pub struct LogFile {
+    name: String,
+    path: String,
+    regex: Regex,
+    file: Option<File>,
+    inode: u64,
+    offset: u64,
+    head: Vec<u8>, //the first bytes of the file the last time we looked
+    partial: Vec<u8>, //bytes of the last line if it hasn't been terminated with a newline yet
+}
+
+impl LogFile {
+    pub fn new(name: &str, path: &str, regex: &str) -> Result<LogFile,String> {
+        let regex = Regex::new(regex).map_err(|e| format!("Invalid regex for log file {}: {}", name, e))?;
+        Ok(LogFile {
+            name: name.to_string(),
+            path: path.to_string(),
+            regex,
+            file: None,
+            inode: 0,
+            offset: 0,
+            head: Vec::new(),
+            partial: Vec::new(),
+        })
+    }
+
+    //opens the file at self.path. When from_end is set the existing content is skipped
+    //so that we only report lines written after yam started
+    pub async fn open(&mut self, from_end: bool) -> Result<(),String> {
+        let mut file = File::open(&self.path).await.map_err(|e| e.to_string())?;
+        let metadata = file.metadata().await.map_err(|e| e.to_string())?;
+        self.offset = if from_end { metadata.len() } else { 0 };
+        self.head = read_head(&mut file).await?;
+        file.seek(SeekFrom::Start(self.offset)).await.map_err(|e| e.to_string())?;
+        self.inode = metadata.ino();
+        self.partial.clear();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    //reads everything between the saved offset and the end of the open file
+    async fn read_new_lines(&mut self) -> Result<Vec<String>,String> {
+        let file = match self.file.as_mut() {
+            None => return Ok(Vec::new()),
+            Some(f) => f
+        };
+
+        //the file got truncated in place (copytruncate style rotation). start over. When it
+        //has grown past the old offset again since the last poll only its first bytes tell
+        let len = file.metadata().await.map_err(|e| e.to_string())?.len();
+        let head = read_head(file).await?;
+        if len < self.offset || !head.starts_with(&self.head) {
+            log::info!("{} was truncated. Reading from the start", self.path);
+            self.offset = 0;
+            self.partial.clear();
+        }
+        self.head = head;
+        file.seek(SeekFrom::Start(self.offset)).await.map_err(|e| e.to_string())?;
+
+        let mut buf = Vec::new();
+        let read = file.read_to_end(&mut buf).await.map_err(|e| e.to_string())?;
+        self.offset += read as u64;
+        self.partial.extend_from_slice(&buf);
+
+        let mut lines = Vec::new();
+        while let Some(pos) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            lines.push(line.trim_end_matches(&['\n', '\r'][..]).to_string());
+        }
+        while self.partial.len() >= MAX_LINE_BYTES {
+            log::warn!("{} has a line longer than {} bytes. Checking it in pieces", self.path, MAX_LINE_BYTES);
+            let piece: Vec<u8> = self.partial.drain(..MAX_LINE_BYTES).collect();
+            lines.push(String::from_utf8_lossy(&piece).to_string());
+        }
+        Ok(lines)
+    }
+
+    //returns the lines appended to the file since the last poll that match the regex
+    pub async fn poll(&mut self) -> Result<Vec<String>,String> {
+        if self.file.is_none() {
+            //the file didn't exist the last time we looked so everything in it is new
+            self.open(false).await?;
+        }
+        let mut lines = self.read_new_lines().await?;
+
+        //the file got renamed and a new one created in its place. The old handle has been
+        //read to the end so switch over to the new file
+        match async_std::fs::metadata(&self.path).await {
+            Err(_e) => {
+                log::debug!("{} does not exist right now. Waiting for it to be recreated", self.path);
+            },
+            Ok(m) => {
+                if m.ino() != self.inode {
+                    log::info!("{} was rotated. Opening the new file", self.path);
+                    self.open(false).await?;
+                    lines.append(&mut self.read_new_lines().await?);
+                }
+            }
+        }
+
+        Ok(lines.into_iter().filter(|l| self.regex.is_match(l)).collect())
+    }
+}
+
+//reads up to HEAD_BYTES from the start of file. The caller seeks back to where it wants to read
+async fn read_head(file: &mut File) -> Result<Vec<u8>,String> {
+    file.seek(SeekFrom::Start(0)).await.map_err(|e| e.to_string())?;
+    let mut head = vec![0; HEAD_BYTES];
+    let mut filled = 0;
+    while filled < HEAD_BYTES {
+        match file.read(&mut head[filled..]).await.map_err(|e| e.to_string())? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    head.truncate(filled);
+    Ok(head)
+}
+
+//tails one log file on its own interval forever
+async fn watch_log_file(config: &Configuration, mut log_file: LogFile, interval: u64, alert_tx: AlertSender) {
+    //a file that stays missing would warn on every interval otherwise. Warn when the error
+    //changes and keep the repeats at debug level
+    let mut last_error = None;
+    if let Err(e) = log_file.open(true).await {
+        log::warn!("Could not open log file {}: {}", log_file.path, e);
+        last_error = Some(e);
+    }
+
+    let mut schedule = Schedule::every_seconds(interval);
+    loop {
+        schedule.tick().await;
+        let lines = match log_file.poll().await {
+            Err(e) => {
+                if last_error.as_ref() == Some(&e) {
+                    log::debug!("Still could not read log file {}: {}", log_file.path, e);
+                } else {
+                    log::warn!("Could not read log file {}: {}", log_file.path, e);
+                    last_error = Some(e);
+                }
+                continue;
+            },
+            Ok(l) => l
+        };
+        if last_error.take().is_some() {
+            log::info!("Log file {} can be read again", log_file.path);
+        }
+
+        for line in lines {
+            log::info!("{} matched: {}", log_file.name, line);
+            if config.is_notify_configured() {
+                let alert = Alert::new(MonitorKind::Log, &log_file.name, format!("{}:{}", log_file.name, line))
+                    .with_severity(Severity::Warning);
+                if let Err(e) = alert_tx.send(alert) {
+                    log::error!("Could not queue the alert:{}",e);
+                }
+            }
+        }
+    }
+}
+
+pub async fn log_mon_start(config: Arc<Configuration>, alert_tx: AlertSender) -> Result<(),String> {
+    let config_log_files = match &config.log_files {
+        None => {
+            log::error!("log_mon got passed a configuration where log_files has not been set");
+            return Err("log_files is not set".to_string())
+        },
+        Some(l) => l
+    };
+
+    let mut watches = Vec::new();
+    for log_file in config_log_files.iter() {
+        let lf = LogFile::new(&log_file.name, &log_file.path, &log_file.regex)?;
+        watches.push(watch_log_file(&config, lf, log_file.interval, alert_tx.clone()));
+    }
+
+    join_all(watches).await;
+    Err("log_mon is exiting. This should never happen".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogFile;
+    use std::{fs, io::Write, path::PathBuf};
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("yam_log_mon_{}_{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn append(path: &PathBuf, s: &str) {
+        let mut f = fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        f.write_all(s.as_bytes()).unwrap();
+    }
+
+    #[async_std::test]
+    async fn only_new_matching_lines_are_reported() {
+        let path = temp_log("new_lines");
+        append(&path, "ERROR before start\n");
+        let mut lf = LogFile::new("app", path.to_str().unwrap(), "ERROR").unwrap();
+        lf.open(true).await.unwrap();
+
+        append(&path, "INFO fine\nERROR one\nERROR tw");
+        assert_eq!(lf.poll().await.unwrap(), vec!["ERROR one"]);
+        append(&path, "o\n");
+        assert_eq!(lf.poll().await.unwrap(), vec!["ERROR two"]);
+        assert!(lf.poll().await.unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn long_lines_are_checked_in_pieces() {
+        let path = temp_log("long_line");
+        let mut lf = LogFile::new("app", path.to_str().unwrap(), "ERROR").unwrap();
+        append(&path, "");
+        lf.open(true).await.unwrap();
+
+        append(&path, &format!("{}ERROR", "x".repeat(super::MAX_LINE_BYTES + 10)));
+        assert!(lf.poll().await.unwrap().is_empty());
+        assert_eq!(lf.partial.len(), 15);
+        append(&path, "\n");
+        assert_eq!(lf.poll().await.unwrap(), vec!["xxxxxxxxxxERROR"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn survives_truncation_and_rename() {
+        let path = temp_log("rotation");
+        let rotated = path.with_extension("log.1");
+        append(&path, "ERROR old\n");
+        let mut lf = LogFile::new("app", path.to_str().unwrap(), "ERROR").unwrap();
+        lf.open(true).await.unwrap();
+
+        fs::write(&path, "ERROR x\n").unwrap(); //truncated and rewritten shorter
+        assert_eq!(lf.poll().await.unwrap(), vec!["ERROR x"]);
+
+        fs::write(&path, "ERROR truncated and grown back\n").unwrap(); //past the old offset between polls
+        assert_eq!(lf.poll().await.unwrap(), vec!["ERROR truncated and grown back"]);
+
+        append(&path, "ERROR before rename\n");
+        fs::rename(&path, &rotated).unwrap();
+        assert_eq!(lf.poll().await.unwrap(), vec!["ERROR before rename"]);
+
+        append(&path, "ERROR after rename\n");
+        assert_eq!(lf.poll().await.unwrap(), vec!["ERROR after rename"]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+    }
+}
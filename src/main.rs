@@ -1,18 +1,18 @@
 //use futures::join;
-use std::{
-        sync::{
-            Arc,
-            mpsc::{
-                channel,
-            }
-        },
-};
+use std::sync::Arc;
 use async_std::task;
+use yam_lib::alert::alert_channel;
 use yam_lib::configuration::Configuration;
-use yam_lib::mysql_mon::mysql_mon_start;
+use yam_lib::db_mon::db_mon_start;
 use yam_lib::web_mon::web_mon_start;
-//use yam_lib::log_mon::log_mon_start;
-use yam_lib::slack::start_slack_poster;
+use yam_lib::log_mon::log_mon_start;
+use yam_lib::cert_mon::cert_mon_start;
+use yam_lib::tcp_mon::tcp_mon_start;
+use yam_lib::dns_mon::dns_mon_start;
+use yam_lib::process_mon::process_mon_start;
+use yam_lib::system_mon::system_mon_start;
+use yam_lib::nginx::nginx_mon_start;
+use yam_lib::notifier::{build_notifier, start_dispatcher};
 
 
 
@@ -40,25 +40,76 @@ async fn main() {
     };
 
     let config_arc = Arc::new(config);
-    let (slack_tx, slack_rx) = channel();
+    let (alert_tx, alert_rx) = alert_channel(config_arc.alert_queue_size, config_arc.alert_queue_full);
 
     let mut handles = Vec::new();
 
-    if config_arc.is_db_configured() {
+    for database in config_arc.databases.iter() {
+        let ca = config_arc.clone();
+        let db = database.clone();
+        let tx = alert_tx.clone();
+        let handle = task::spawn(async move {
+            db_mon_start(ca,db,tx).await});
+        handles.push(handle);
+    }
+    if config_arc.is_log_configured() {
+        let ca = config_arc.clone();
+        let tx = alert_tx.clone();
+        let handle = task::spawn(async move {
+            log_mon_start(ca,tx).await});
+        handles.push(handle);
+    }
+    if config_arc.is_cert_configured() {
+        let ca = config_arc.clone();
+        let tx = alert_tx.clone();
+        let handle = task::spawn(async move {
+            cert_mon_start(ca,tx).await});
+        handles.push(handle);
+    }
+    if config_arc.is_tcp_configured() {
+        let ca = config_arc.clone();
+        let tx = alert_tx.clone();
+        let handle = task::spawn(async move {
+            tcp_mon_start(ca,tx).await});
+        handles.push(handle);
+    }
+    if config_arc.is_dns_configured() {
+        let ca = config_arc.clone();
+        let tx = alert_tx.clone();
+        let handle = task::spawn(async move {
+            dns_mon_start(ca,tx).await});
+        handles.push(handle);
+    }
+    if config_arc.is_process_configured() {
+        let ca = config_arc.clone();
+        let tx = alert_tx.clone();
+        let handle = task::spawn(async move {
+            process_mon_start(ca,tx).await});
+        handles.push(handle);
+    }
+    if config_arc.is_system_configured() {
+        let ca = config_arc.clone();
+        let tx = alert_tx.clone();
+        let handle = task::spawn(async move {
+            system_mon_start(ca,tx).await});
+        handles.push(handle);
+    }
+    if config_arc.is_nginx_configured() {
         let ca = config_arc.clone();
-        let tx = slack_tx.clone();
+        let tx = alert_tx.clone();
         let handle = task::spawn(async move {
-            mysql_mon_start(ca,tx).await});
+            nginx_mon_start(ca,tx).await});
         handles.push(handle);
     }
-    if config_arc.is_slack_configured() {
-        let su = config_arc.slack_url.as_ref().unwrap().clone();
+    if config_arc.is_notify_configured() {
+        let notifiers = config_arc.notifiers.iter().map(build_notifier).collect();
+        let (size, when_full) = (config_arc.alert_queue_size, config_arc.alert_queue_full);
         let handle = task::spawn(async move {
-            start_slack_poster(su, slack_rx).await});
+            start_dispatcher(notifiers, alert_rx, size, when_full).await});
         handles.push(handle);
     }
 
-    let tx = slack_tx.clone();
+    let tx = alert_tx.clone();
     let ca = config_arc.clone();
     let handle = task::spawn(async move {
         web_mon_start(ca,tx).await});
@@ -1 +1,396 @@
-//start
+use async_std::future::timeout;
+use futures::future::join_all;
+use regex::Regex;
+use std::{
+        sync::Arc,
+        time::{
+            Duration,
+            Instant,
+        },
+};
+use crate::alert::{Alert, AlertSender, MonitorKind};
+use crate::configuration::{Configuration, NginxConfig};
+use crate::log_mon::LogFile;
+use crate::scheduler::Schedule;
+use crate::status::{ResendTimer, State, StateTracker, Transition};
+
+//the combined log format, optionally followed by $request_time
+const COMBINED_LOG_REGEX: &str = r#"^\S+ \S+ \S+ \[[^\]]*\] "[^"]*" (?P<status>\d{3}) \S+(?: "[^"]*" "[^"]*")?(?: (?P<request_time>\d+(?:\.\d+)?))?"#;
+
+//what the stub_status page says. accepts, handled and requests count up from when nginx started
+#[derive(Clone, Debug, Default, PartialEq)]
+struct StubStatus {
+    active: u64,
+    accepts: u64,
+    handled: u64,
+    requests: u64,
+    reading: u64,
+    writing: u64,
+    waiting: u64,
+}
+
+//reads a stub_status page:
+//Active connections: 291
+//server accepts handled requests
+// 16630948 16630948 31070465
+//Reading: 6 Writing: 179 Waiting: 106
+fn parse_stub_status(text: &str) -> Result<StubStatus,String> {
+    let numbers: Vec<u64> = text.split_whitespace().filter_map(|w| w.parse().ok()).collect();
+    if !text.starts_with("Active connections:") || numbers.len() != 7 {
+        return Err(format!("Not a stub_status page: {:?}", text.chars().take(200).collect::<String>()));
+    }
+    Ok(StubStatus {
+        active: numbers[0],
+        accepts: numbers[1],
+        handled: numbers[2],
+        requests: numbers[3],
+        reading: numbers[4],
+        writing: numbers[5],
+        waiting: numbers[6],
+    })
+}
+
+//the value below which percent of the sorted values are, by the nearest rank
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    let rank = (percent/100.0*sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+//the status codes and request times of the access log lines written since the last check
+#[derive(Debug, Default, PartialEq)]
+struct AccessLogWindow {
+    requests: u64,
+    errors: u64, //5xx responses
+    request_times: Vec<f64>, //milliseconds, sorted. Empty when the log format doesn't have them
+}
+
+impl AccessLogWindow {
+    fn new(regex: &Regex, lines: &[String]) -> AccessLogWindow {
+        let mut window = AccessLogWindow::default();
+        for captures in lines.iter().filter_map(|l| regex.captures(l)) {
+            let status = match captures.name("status").and_then(|s| s.as_str().parse::<u16>().ok()) {
+                None => continue,
+                Some(s) => s
+            };
+            window.requests += 1;
+            if (500..600).contains(&status) {
+                window.errors += 1;
+            }
+            if let Some(t) = captures.name("request_time").and_then(|t| t.as_str().parse::<f64>().ok()) {
+                window.request_times.push(t*1000.0);
+            }
+        }
+        window.request_times.sort_by(|a, b| a.total_cmp(b));
+        window
+    }
+}
+
+struct NginxCheck {
+    config: NginxConfig,
+    access_log: Option<LogFile>,
+    log_regex: Regex,
+    last_status: Option<(Instant, StubStatus)>,
+    status_state: StateTracker,
+    error_state: StateTracker,
+    latency_state: StateTracker,
+}
+
+impl NginxCheck {
+    fn new(config: NginxConfig) -> Result<NginxCheck,String> {
+        let log_regex = Regex::new(config.log_regex.as_deref().unwrap_or(COMBINED_LOG_REGEX))
+            .map_err(|e| format!("Invalid log_regex for nginx {}: {}", config.name, e))?;
+        if !log_regex.capture_names().any(|n| n == Some("status")) {
+            return Err(format!("log_regex of nginx {} has no status group", config.name));
+        }
+        let access_log = match &config.access_log {
+            None => None,
+            Some(path) => Some(LogFile::new(&config.name, path, log_regex.as_str())?),
+        };
+        Ok(NginxCheck {
+            config,
+            access_log,
+            log_regex,
+            last_status: None,
+            status_state: StateTracker::default(),
+            error_state: StateTracker::default(),
+            latency_state: StateTracker::default(),
+        })
+    }
+
+    //skips what's already in the access log so only requests made after yam started count
+    async fn start(&mut self) {
+        if let Some(access_log) = self.access_log.as_mut() {
+            if let Err(e) = access_log.open(true).await {
+                log::warn!("Could not open the access log of nginx {}: {}", self.config.name, e);
+            }
+        }
+    }
+
+    async fn fetch_stub_status(&self, url: &str) -> Result<StubStatus,String> {
+        let time_limit = Duration::from_secs(self.config.timeout);
+        let text = timeout(time_limit, async {
+            let mut response = surf::get(url).await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("http status is {}", response.status()));
+            }
+            response.body_string().await.map_err(|e| e.to_string())
+        }).await.unwrap_or_else(|_| Err(format!("timed out after {} seconds", time_limit.as_secs())))?;
+        parse_stub_status(&text)
+    }
+
+    //reads stub_status and returns the state of the connections and the request rate since the
+    //last check, and a description of them
+    async fn connections(&mut self, url: &str, now: Instant) -> (State, String) {
+        let status = match self.fetch_stub_status(url).await {
+            Err(e) => {
+                self.last_status = None;
+                return (State::Critical, format!("stub_status could not be read. {}", e))
+            },
+            Ok(s) => s
+        };
+        let mut state = self.config.active.state(status.active as f64);
+        let mut description = format!("{} active connections ({} reading, {} writing, {} waiting)",
+            status.active, status.reading, status.writing, status.waiting);
+
+        //the counters go back to 0 when nginx restarts so there's nothing to compare against
+        let last = self.last_status.replace((now, status.clone()))
+            .filter(|(_, last)| status.requests >= last.requests && status.accepts >= last.accepts);
+        if let Some((then, last)) = last {
+            let seconds = now.duration_since(then).as_secs_f64();
+            if seconds > 0.0 {
+                let rate = (status.requests - last.requests) as f64/seconds;
+                state = state.max(self.config.requests_per_second.state(rate));
+                description += &format!(", {:.1} requests/s", rate);
+            }
+            //nginx only doesn't handle a connection it accepted when it's run out of worker_connections
+            let dropped = (status.accepts - last.accepts).saturating_sub(status.handled.saturating_sub(last.handled));
+            if dropped > 0 {
+                state = state.max(State::Warn);
+                description += &format!(", {} connections dropped", dropped);
+            }
+        }
+        (state, description)
+    }
+}
+
+//sends an alert when the state of one of the checks of an nginx changes or it's time to resend it
+fn report(tracker: &mut StateTracker, what: &str, state: State, description: &str, resend: bool, alert_tx: Option<&AlertSender>) {
+    match state {
+        State::Ok => log::info!("{} {} {}", what, description, state),
+        State::Warn => log::warn!("{} {} {}", what, description, state),
+        State::Critical => log::error!("{} {} {}", what, description, state),
+    }
+
+    let previous = tracker.current();
+    let duration = tracker.in_state_for();
+    let msg = match tracker.update(state) {
+        Some(Transition { from: Some(from), .. }) => format!("[nginx] {} is {} (was {}): {}", what, state, from, description),
+        Some(_) => format!("[nginx] {} is {}: {}", what, state, description),
+        None if resend => format!("[nginx] {} is still {}: {}", what, state, description),
+        None => return
+    };
+
+    if let Some(tx) = alert_tx {
+        if let Err(e) = tx.send(Alert::new(MonitorKind::Nginx, what, msg).with_state(state, previous).with_duration(duration)) {
+            log::error!("Could not queue the alert:{}",e);
+        }
+    }
+}
+
+//checks stub_status and the access log once and returns the worst state of them
+async fn check_nginx(nginx: &mut NginxCheck, now: Instant, resend: bool, alert_tx: Option<&AlertSender>) -> State {
+    let name = nginx.config.name.clone();
+    let mut worst = State::Ok;
+
+    if let Some(url) = nginx.config.status_url.clone() {
+        let (state, description) = nginx.connections(&url, now).await;
+        report(&mut nginx.status_state, &name, state, &description, resend, alert_tx);
+        worst = worst.max(state);
+    }
+
+    let lines = match nginx.access_log.as_mut() {
+        None => return worst,
+        Some(access_log) => access_log.poll().await
+    };
+    let window = match lines {
+        Err(e) => {
+            let description = format!("the access log could not be read. {}", e);
+            report(&mut nginx.error_state, &format!("{} 5xx rate", name), State::Warn, &description, resend, alert_tx);
+            return worst.max(State::Warn)
+        },
+        Ok(lines) => AccessLogWindow::new(&nginx.log_regex, &lines)
+    };
+
+    let rate = if window.requests > 0 { window.errors as f64/window.requests as f64*100.0 } else { 0.0 };
+    let state = nginx.config.error_rate.state(rate);
+    let description = format!("{:.1}% of {} requests", rate, window.requests);
+    report(&mut nginx.error_state, &format!("{} 5xx rate", name), state, &description, resend, alert_tx);
+    worst = worst.max(state);
+
+    //there's nothing to judge the latency on without requests that logged their time. Without
+    //slow requests the latency is fine, so a WARN from an earlier window clears
+    if window.request_times.is_empty() {
+        if window.requests > 0 && !nginx.config.latency.is_empty() {
+            log::warn!("The access log of nginx {} has no request times", name);
+        }
+        report(&mut nginx.latency_state, &format!("{} latency", name), State::Ok, "no requests with a request time", resend, alert_tx);
+        return worst;
+    }
+    let times = &window.request_times;
+    let p = nginx.config.latency_percentile;
+    let value = percentile(times, p);
+    let state = nginx.config.latency.state(value);
+    let description = format!("p{} is {:.0} ms (p50 {:.0} ms, p99 {:.0} ms) over {} requests",
+        p, value, percentile(times, 50.0), percentile(times, 99.0), times.len());
+    report(&mut nginx.latency_state, &format!("{} latency", name), state, &description, resend, alert_tx);
+    worst.max(state)
+}
+
+//checks one nginx on its own interval forever
+async fn watch_nginx(config: &Configuration, mut nginx: NginxCheck, alert_tx: AlertSender) {
+    nginx.start().await;
+    let mut schedule = Schedule::every_seconds(nginx.config.interval);
+    let mut resend_timer = ResendTimer::new(config.resend_status_minutes);
+    let alert_tx = if config.is_notify_configured() { Some(&alert_tx) } else { None };
+
+    loop {
+        schedule.tick().await;
+        let resend = resend_timer.due();
+        if resend {
+            log::info!("nginx_mon is resending the status of {}", nginx.config.name);
+        }
+        check_nginx(&mut nginx, Instant::now(), resend, alert_tx).await;
+    }
+}
+
+pub async fn nginx_mon_start(config: Arc<Configuration>, alert_tx: AlertSender) -> Result<(),String> {
+    let nginx_checks = match &config.nginx {
+        None => return Err("nginx monitoring is not configured".to_string()),
+        Some(n) => n
+    };
+
+    let mut watches = Vec::new();
+    for nginx in nginx_checks.iter() {
+        watches.push(watch_nginx(&config, NginxCheck::new(nginx.clone())?, alert_tx.clone()));
+    }
+    join_all(watches).await;
+    Err("nginx_mon is exiting. This should never happen".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_nginx, parse_stub_status, NginxCheck, StubStatus};
+    use crate::alert::{alert_channel, QueueFull};
+    use crate::configuration::NginxConfig;
+    use crate::status::{State, Thresholds};
+    use crate::test_support::http_stand_in;
+    use std::{
+        fs,
+        io::Write,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    //a stand in nginx that serves whatever is in page as its stub_status
+    fn stub_status_server(page: Arc<Mutex<String>>) -> String {
+        http_stand_in("/nginx_status", Duration::from_millis(0), move |_| {
+            let page = page.lock().unwrap().clone();
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", page.len(), page)
+        })
+    }
+
+    fn page(active: u64, accepts: u64, handled: u64, requests: u64) -> String {
+        format!("Active connections: {} \nserver accepts handled requests\n {} {} {} \nReading: 1 Writing: 2 Waiting: {} \n",
+            active, accepts, handled, requests, active - 3)
+    }
+
+    fn nginx_config(status_url: Option<String>, access_log: Option<String>) -> NginxConfig {
+        NginxConfig {
+            name: "web1".to_string(),
+            status_url,
+            active: Thresholds { warn_above: Some(100.0), ..Thresholds::default() },
+            requests_per_second: Thresholds::default(),
+            access_log,
+            log_regex: None,
+            error_rate: Thresholds { warn_above: Some(10.0), critical_above: Some(40.0), ..Thresholds::default() },
+            latency_percentile: 95.0,
+            latency: Thresholds { warn_above: Some(500.0), ..Thresholds::default() },
+            interval: 60,
+            timeout: 2,
+        }
+    }
+
+    #[test]
+    fn stub_status_is_parsed() {
+        assert_eq!(parse_stub_status(&page(10, 100, 99, 250)).unwrap(), StubStatus {
+            active: 10, accepts: 100, handled: 99, requests: 250, reading: 1, writing: 2, waiting: 7,
+        });
+        assert!(parse_stub_status("<html>Welcome to nginx!</html>").is_err());
+    }
+
+    #[async_std::test]
+    async fn connections_and_request_rate_are_checked() {
+        let status = Arc::new(Mutex::new(page(10, 100, 100, 250)));
+        let url = stub_status_server(status.clone());
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut nginx = NginxCheck::new(nginx_config(Some(url), None)).unwrap();
+        let start = Instant::now();
+
+        assert_eq!(check_nginx(&mut nginx, start, false, Some(&tx)).await, State::Ok);
+        *status.lock().unwrap() = page(10, 130, 130, 850);
+        assert_eq!(check_nginx(&mut nginx, start + Duration::from_secs(60), false, Some(&tx)).await, State::Ok);
+        *status.lock().unwrap() = page(150, 200, 190, 1450);
+        assert_eq!(check_nginx(&mut nginx, start + Duration::from_secs(120), false, Some(&tx)).await, State::Warn);
+        nginx.config.status_url = Some("http://127.0.0.1:1/nginx_status".to_string());
+        assert_eq!(check_nginx(&mut nginx, start + Duration::from_secs(180), false, Some(&tx)).await, State::Critical);
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert_eq!(sent[0], "[nginx] web1 is OK: 10 active connections (1 reading, 2 writing, 7 waiting)");
+        assert_eq!(sent[1], "[nginx] web1 is WARN (was OK): 150 active connections (1 reading, 2 writing, 147 waiting), 10.0 requests/s, 10 connections dropped");
+        assert!(sent[2].starts_with("[nginx] web1 is CRITICAL (was WARN): stub_status could not be read."), "{}", sent[2]);
+        assert_eq!(sent.len(), 3);
+    }
+
+    #[async_std::test]
+    async fn access_log_errors_and_latency_are_checked() {
+        let path = std::env::temp_dir().join(format!("yam_nginx_{}.log", std::process::id()));
+        fs::write(&path, "10.0.0.1 - - [18/Oct/2026:10:00:00 +0000] \"GET / HTTP/1.1\" 500 0 \"-\" \"curl\" 9.000\n").unwrap();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut nginx = NginxCheck::new(nginx_config(None, Some(path.to_str().unwrap().to_string()))).unwrap();
+        nginx.start().await;
+
+        let line = |status: u16, time: &str| format!("10.0.0.1 - - [18/Oct/2026:10:00:01 +0000] \"GET /a HTTP/1.1\" {} 612 \"-\" \"curl/7.68.0\" {}\n", status, time);
+        let mut log = String::new();
+        for i in 0..19 {
+            log += &line(200, &format!("0.{:03}", 10 + i));
+        }
+        log += &line(502, "0.900");
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(log.as_bytes()).unwrap();
+        assert_eq!(check_nginx(&mut nginx, Instant::now(), false, Some(&tx)).await, State::Ok);
+
+        let mut log = String::new();
+        for _ in 0..3 {
+            log += &line(200, "0.020");
+        }
+        log += &line(503, "1.200");
+        log += "not an access log line\n";
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(log.as_bytes()).unwrap();
+        assert_eq!(check_nginx(&mut nginx, Instant::now(), false, Some(&tx)).await, State::Warn);
+        //a slow window followed by one without requests
+        assert_eq!(check_nginx(&mut nginx, Instant::now(), false, Some(&tx)).await, State::Ok);
+        assert_eq!(check_nginx(&mut nginx, Instant::now(), true, Some(&tx)).await, State::Ok);
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert_eq!(sent, vec![
+            "[nginx] web1 5xx rate is OK: 5.0% of 20 requests",
+            "[nginx] web1 latency is OK: p95 is 28 ms (p50 19 ms, p99 900 ms) over 20 requests",
+            "[nginx] web1 5xx rate is WARN (was OK): 25.0% of 4 requests",
+            "[nginx] web1 latency is WARN (was OK): p95 is 1200 ms (p50 20 ms, p99 1200 ms) over 4 requests",
+            "[nginx] web1 5xx rate is OK (was WARN): 0.0% of 0 requests",
+            "[nginx] web1 latency is OK (was WARN): no requests with a request time",
+            "[nginx] web1 5xx rate is still OK: 0.0% of 0 requests",
+            "[nginx] web1 latency is still OK: no requests with a request time",
+        ]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use async_std::future::timeout;
+use futures::future::{join, join_all, BoxFuture};
+use std::time::Duration;
+use crate::alert::{alert_channel, Alert, AlertReceiver, QueueFull};
+use crate::configuration::{NotifierConfig, NotifierKind};
+use crate::email::EmailNotifier;
+use crate::slack::SlackNotifier;
+use crate::webhook::WebhookNotifier;
+
+//how often notifiers get to send what they're holding on to when no alerts come in
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+//somewhere alerts can be delivered. Every kind of notifier implements this so the dispatcher
+//below only has to be written once
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &str;
+    //delivers one alert, or queues it to be sent by a later flush. Errors are logged
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(),String>>;
+    //sends whatever is due of what notify queued. Called after every alert and every FLUSH_INTERVAL
+    fn flush(&self) -> BoxFuture<'_, Result<(),String>> {
+        Box::pin(async { Ok(()) })
+    }
+    //the alerts notify queued that haven't been sent yet
+    fn undelivered(&self) -> BoxFuture<'_, Vec<Alert>> {
+        Box::pin(async { Vec::new() })
+    }
+}
+
+//the name of the machine yam runs on, for notifiers to say where an alert came from
+pub fn hostname() -> String {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return "unknown host".to_string();
+    }
+    let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..end]).to_string()
+}
+
+//makes the notifier a [[notifiers]] entry describes
+pub fn build_notifier(config: &NotifierConfig) -> Box<dyn Notifier> {
+    match &config.kind {
+        NotifierKind::Slack(slack) => Box::new(SlackNotifier::new(&config.name, slack)),
+        NotifierKind::Email(email) => Box::new(EmailNotifier::new(&config.name, email)),
+        NotifierKind::Webhook(webhook) => Box::new(WebhookNotifier::new(&config.name, webhook)),
+    }
+}
+
+//hands the alerts of one notifier to it one at a time
+async fn deliver(notifier: &dyn Notifier, alert_rx: AlertReceiver) {
+    loop {
+        match timeout(FLUSH_INTERVAL, alert_rx.recv()).await {
+            Ok(None) => break,
+            Ok(Some(alert)) => {
+                if let Err(e) = notifier.notify(&alert).await {
+                    log::error!("Could not send the alert of {} to {}: {}", alert.check_id, notifier.name(), e);
+                }
+            },
+            Err(_) => {}
+        }
+        if let Err(e) = notifier.flush().await {
+            log::error!("Could not send the alerts {} is holding on to: {}", notifier.name(), e);
+        }
+    }
+
+    //no more alerts are coming. Give the ones still waiting a last chance and say which are lost
+    if let Err(e) = notifier.flush().await {
+        log::error!("Could not send the alerts {} is holding on to: {}", notifier.name(), e);
+    }
+    let undelivered = notifier.undelivered().await;
+    if !undelivered.is_empty() {
+        let check_ids: Vec<&str> = undelivered.iter().map(|a| a.check_id.as_str()).collect();
+        log::error!("{} alerts were never sent to {}: {}", undelivered.len(), notifier.name(), check_ids.join(", "));
+    }
+}
+
+//delivers every alert to every notifier. Each notifier has its own queue so a slow one only
+//holds up itself, and alerts it can't keep up with are dropped the same way as on the queue
+//from the monitors
+pub async fn start_dispatcher(notifiers: Vec<Box<dyn Notifier>>, alert_rx: AlertReceiver, queue_size: usize, when_full: QueueFull) -> Result<(),String> {
+    let (senders, deliveries): (Vec<_>, Vec<_>) = notifiers.iter()
+        .map(|n| {
+            let (tx, rx) = alert_channel(queue_size, when_full);
+            (tx, deliver(n.as_ref(), rx))
+        })
+        .unzip();
+
+    let dispatch = async move {
+        while let Some(alert) = alert_rx.recv().await {
+            for tx in senders.iter() {
+                if let Err(e) = tx.send(alert.clone()) {
+                    log::error!("Could not queue the alert of {}: {}", alert.check_id, e);
+                }
+            }
+        }
+    };
+    join(dispatch, join_all(deliveries)).await;
+    Err("the dispatcher is exiting. This should never happen".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{start_dispatcher, Notifier};
+    use crate::alert::{alert_channel, Alert, MonitorKind, QueueFull};
+    use futures::future::BoxFuture;
+    use std::sync::{Arc, Mutex};
+
+    //remembers the messages it was given and fails when told to
+    struct FakeNotifier {
+        name: String,
+        fail: bool,
+        received: Arc<Mutex<Vec<String>>>,
+        flushes: Arc<Mutex<usize>>,
+    }
+
+    impl Notifier for FakeNotifier {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(),String>> {
+            Box::pin(async move {
+                self.received.lock().unwrap().push(alert.message.clone());
+                if self.fail { Err("down".to_string()) } else { Ok(()) }
+            })
+        }
+
+        fn flush(&self) -> BoxFuture<'_, Result<(),String>> {
+            Box::pin(async move {
+                *self.flushes.lock().unwrap() += 1;
+                Ok(())
+            })
+        }
+    }
+
+    fn fake(name: &str, fail: bool) -> FakeNotifier {
+        FakeNotifier {
+            name: name.to_string(),
+            fail,
+            received: Arc::new(Mutex::new(Vec::new())),
+            flushes: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    #[async_std::test]
+    async fn every_alert_goes_to_every_notifier() {
+        let fakes: Vec<FakeNotifier> = (0..2).map(|i| fake(&i.to_string(), i == 0)).collect();
+        let received: Vec<Arc<Mutex<Vec<String>>>> = fakes.iter().map(|f| f.received.clone()).collect();
+        let notifiers: Vec<Box<dyn Notifier>> = fakes.into_iter().map(|f| Box::new(f) as Box<dyn Notifier>).collect();
+        let (tx, rx) = alert_channel(10, QueueFull::DropOldest);
+        for n in 0..3 {
+            tx.send(Alert::new(MonitorKind::Tcp, "redis", n.to_string())).unwrap();
+        }
+        drop(tx);
+
+        //returns once the queue is empty and closed
+        assert!(start_dispatcher(notifiers, rx, 10, QueueFull::DropOldest).await.is_err());
+        for r in received {
+            assert_eq!(*r.lock().unwrap(), vec!["0", "1", "2"]);
+        }
+    }
+
+    #[async_std::test]
+    async fn notifiers_get_a_last_flush_when_the_queue_closes() {
+        let notifier = fake("0", false);
+        let flushes = notifier.flushes.clone();
+        let (tx, rx) = alert_channel(10, QueueFull::DropOldest);
+        drop(tx);
+
+        assert!(start_dispatcher(vec![Box::new(notifier)], rx, 10, QueueFull::DropOldest).await.is_err());
+        assert_eq!(*flushes.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,325 @@
+use async_std::task;
+use futures::future::join_all;
+use regex::Regex;
+use std::{
+        collections::HashMap,
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Instant,
+};
+use crate::alert::{Alert, AlertSender, MonitorKind};
+use crate::configuration::{Configuration, ProcessConfig};
+use crate::scheduler::Schedule;
+use crate::status::{ResendTimer, State, StateTracker, Transition};
+
+//what we need to know about one process, read out of /proc/<pid>
+#[derive(Clone, Debug, PartialEq)]
+struct ProcessInfo {
+    pid: u32,
+    comm: String,
+    cmdline: String,
+    cgroup: String,
+    rss_kb: u64,
+    cpu_ticks: u64, //user and system time so far
+}
+
+fn read_process(dir: &Path, pid: u32) -> Option<ProcessInfo> {
+    let comm = fs::read_to_string(dir.join("comm")).ok()?;
+    let cmdline = fs::read(dir.join("cmdline")).ok()?;
+    let cmdline = cmdline.split(|b| *b == 0)
+        .filter(|a| !a.is_empty())
+        .map(|a| String::from_utf8_lossy(a).to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    //the name in stat can contain spaces and brackets so the fields are counted from the last )
+    let stat = fs::read_to_string(dir.join("stat")).ok()?;
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let ticks = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+    let cpu_ticks = ticks(11)? + ticks(12)?;
+
+    //kernel threads have no VmRSS
+    let rss_kb = fs::read_to_string(dir.join("status")).ok()?
+        .lines()
+        .find_map(|l| l.strip_prefix("VmRSS:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse().ok())
+        .unwrap_or(0);
+
+    Some(ProcessInfo {
+        pid,
+        comm: comm.trim_end().to_string(),
+        cmdline,
+        cgroup: fs::read_to_string(dir.join("cgroup")).unwrap_or_default(),
+        rss_kb,
+        cpu_ticks,
+    })
+}
+
+//every process under root except yam itself. Processes that exit while they're being read are skipped
+fn list_processes(root: &Path) -> Vec<ProcessInfo> {
+    let own_pid = std::process::id();
+    let entries = match fs::read_dir(root) {
+        Err(e) => {
+            log::error!("Could not read {}: {}", root.display(), e);
+            return Vec::new();
+        },
+        Ok(e) => e
+    };
+    entries.filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().and_then(|n| n.parse::<u32>().ok()).map(|pid| (pid, e.path())))
+        .filter(|(pid, _)| *pid != own_pid)
+        .filter_map(|(pid, dir)| read_process(&dir, pid))
+        .collect()
+}
+
+fn clock_ticks_per_second() -> f64 {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 { ticks as f64 } else { 100.0 }
+}
+
+struct ProcessCheck {
+    config: ProcessConfig,
+    comm: Option<Regex>,
+    cmdline: Option<Regex>,
+    proc_root: PathBuf,
+    last_sample: Option<(Instant, HashMap<u32, u64>)>, //cpu ticks of every matching process at the last check
+    state: StateTracker,
+}
+
+impl ProcessCheck {
+    fn new(config: ProcessConfig, proc_root: &Path) -> Result<ProcessCheck,String> {
+        let regex = |r: &Option<String>, what: &str| -> Result<Option<Regex>,String> {
+            match r {
+                None => Ok(None),
+                Some(r) => Regex::new(r).map(Some).map_err(|e| format!("Invalid {} regex for process {}: {}", what, config.name, e))
+            }
+        };
+        Ok(ProcessCheck {
+            comm: regex(&config.comm, "comm")?,
+            cmdline: regex(&config.cmdline, "cmdline")?,
+            config,
+            proc_root: proc_root.to_path_buf(),
+            last_sample: None,
+            state: StateTracker::default(),
+        })
+    }
+
+    fn matches(&self, process: &ProcessInfo) -> bool {
+        self.comm.as_ref().is_none_or(|r| r.is_match(&process.comm))
+            && self.cmdline.as_ref().is_none_or(|r| r.is_match(&process.cmdline))
+            && self.config.unit.as_ref().is_none_or(|unit| {
+                process.cgroup.lines().any(|l| l.rsplit(':').next().is_some_and(|path| path.split('/').any(|p| p == unit)))
+            })
+    }
+
+    //works out the state from the matching processes. Cpu use is measured between two checks
+    //so it isn't judged on the first one
+    fn evaluate(&mut self, processes: &[ProcessInfo], now: Instant) -> (State, String) {
+        let matching: Vec<&ProcessInfo> = processes.iter().filter(|p| self.matches(p)).collect();
+        let ticks: HashMap<u32, u64> = matching.iter().map(|p| (p.pid, p.cpu_ticks)).collect();
+        let last_sample = self.last_sample.replace((now, ticks));
+
+        let count = matching.len() as u64;
+        if count < self.config.min_count {
+            return (State::Critical, format!("{} running, expected at least {}", count, self.config.min_count));
+        }
+
+        let mut state = State::Ok;
+        let mut reasons = Vec::new();
+        let mut max_rss = 0.0;
+        let mut max_cpu: Option<f64> = None;
+        for p in matching.iter() {
+            let rss_mb = p.rss_kb as f64/1024.0;
+            let rss_state = self.config.rss_mb.state(rss_mb);
+            if rss_state > State::Ok {
+                reasons.push(format!("pid {} uses {:.0} MB", p.pid, rss_mb));
+            }
+            state = state.max(rss_state);
+            max_rss = f64::max(max_rss, rss_mb);
+
+            let cpu = last_sample.as_ref().and_then(|(then, ticks)| {
+                let seconds = now.duration_since(*then).as_secs_f64();
+                let before = ticks.get(&p.pid)?;
+                if seconds <= 0.0 {
+                    return None;
+                }
+                Some(p.cpu_ticks.saturating_sub(*before) as f64/clock_ticks_per_second()/seconds*100.0)
+            });
+            if let Some(cpu) = cpu {
+                let cpu_state = self.config.cpu_percent.state(cpu);
+                if cpu_state > State::Ok {
+                    reasons.push(format!("pid {} uses {:.1}% cpu", p.pid, cpu));
+                }
+                state = state.max(cpu_state);
+                max_cpu = Some(max_cpu.map_or(cpu, |m| m.max(cpu)));
+            }
+        }
+
+        let mut description = format!("{} running, largest is {:.0} MB", count, max_rss);
+        if let Some(cpu) = max_cpu {
+            description += &format!(", busiest uses {:.1}% cpu", cpu);
+        }
+        if !reasons.is_empty() {
+            description += &format!(": {}", reasons.join(", "));
+        }
+        (state, description)
+    }
+}
+
+//looks at the processes once and sends an alert when the state changes or it's time to resend it
+async fn check_process(check: &mut ProcessCheck, now: Instant, resend: bool, alert_tx: Option<&AlertSender>) -> State {
+    let root = check.proc_root.clone();
+    let processes = task::spawn_blocking(move || list_processes(&root)).await;
+    let (state, description) = check.evaluate(&processes, now);
+    let name = &check.config.name;
+    match state {
+        State::Ok => log::info!("process {} {} {}", name, description, state),
+        State::Warn => log::warn!("process {} {} {}", name, description, state),
+        State::Critical => log::error!("process {} {} {}", name, description, state),
+    }
+
+    let previous = check.state.current();
+    let duration = check.state.in_state_for();
+    let msg = match check.state.update(state) {
+        Some(Transition { from: Some(from), .. }) => format!("[process] {} is {} (was {}): {}", name, state, from, description),
+        Some(_) => format!("[process] {} is {}: {}", name, state, description),
+        None if resend => format!("[process] {} is still {}: {}", name, state, description),
+        None => return state
+    };
+
+    if let Some(tx) = alert_tx {
+        if let Err(e) = tx.send(Alert::new(MonitorKind::Process, name, msg).with_state(state, previous).with_duration(duration)) {
+            log::error!("Could not queue the alert:{}",e);
+        }
+    }
+    state
+}
+
+//checks one set of processes on its own interval forever
+async fn watch_process(config: &Configuration, mut check: ProcessCheck, alert_tx: AlertSender) {
+    let mut schedule = Schedule::every_seconds(check.config.interval);
+    let mut resend_timer = ResendTimer::new(config.resend_status_minutes);
+    let alert_tx = if config.is_notify_configured() { Some(&alert_tx) } else { None };
+
+    loop {
+        schedule.tick().await;
+        let resend = resend_timer.due();
+        if resend {
+            log::info!("process_mon is resending the status of {}", check.config.name);
+        }
+        check_process(&mut check, Instant::now(), resend, alert_tx).await;
+    }
+}
+
+pub async fn process_mon_start(config: Arc<Configuration>, alert_tx: AlertSender) -> Result<(),String> {
+    let processes = match &config.processes {
+        None => return Err("Process monitoring is not configured".to_string()),
+        Some(p) => p
+    };
+
+    let mut watches = Vec::new();
+    for process in processes.iter() {
+        let check = ProcessCheck::new(process.clone(), Path::new("/proc"))?;
+        watches.push(watch_process(&config, check, alert_tx.clone()));
+    }
+    join_all(watches).await;
+    Err("process_mon is exiting. This should never happen".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_process, clock_ticks_per_second, list_processes, ProcessCheck};
+    use crate::alert::{alert_channel, QueueFull};
+    use crate::configuration::ProcessConfig;
+    use crate::status::{State, Thresholds};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    };
+
+    fn fake_proc(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("yam_process_mon_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    //writes the files of one process into a fake /proc
+    fn fake_process(root: &Path, pid: u32, comm: &str, args: &[&str], unit: &str, rss_kb: u64, cpu_ticks: u64) {
+        let dir = root.join(pid.to_string());
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("comm"), format!("{}\n", comm)).unwrap();
+        fs::write(dir.join("cmdline"), args.iter().map(|a| format!("{}\0", a)).collect::<String>()).unwrap();
+        fs::write(dir.join("stat"), format!("{} ({}) S 1 1 1 0 -1 4194560 100 0 0 0 {} 0 0 0 20 0 1 0 100 1000 10", pid, comm, cpu_ticks)).unwrap();
+        fs::write(dir.join("status"), format!("Name:\t{}\nVmRSS:\t  {} kB\n", comm, rss_kb)).unwrap();
+        fs::write(dir.join("cgroup"), format!("0::/system.slice/{}\n", unit)).unwrap();
+    }
+
+    fn process_config(comm: Option<&str>, cmdline: Option<&str>, unit: Option<&str>) -> ProcessConfig {
+        ProcessConfig {
+            name: "workers".to_string(),
+            comm: comm.map(|s| s.to_string()),
+            cmdline: cmdline.map(|s| s.to_string()),
+            unit: unit.map(|s| s.to_string()),
+            min_count: 1,
+            rss_mb: Thresholds::default(),
+            cpu_percent: Thresholds::default(),
+            interval: 60,
+        }
+    }
+
+    #[test]
+    fn processes_are_read_from_proc() {
+        let root = fake_proc("read");
+        fake_process(&root, 42, "my worker (x)", &["/usr/bin/worker", "--queue=emails"], "workers.service", 2048, 150);
+        fs::create_dir_all(root.join("self")).unwrap();
+        let processes = list_processes(&root);
+        assert_eq!(processes.len(), 1);
+        assert_eq!((processes[0].pid, processes[0].comm.as_str()), (42, "my worker (x)"));
+        assert_eq!(processes[0].cmdline, "/usr/bin/worker --queue=emails");
+        assert_eq!((processes[0].rss_kb, processes[0].cpu_ticks), (2048, 150));
+
+        //the real /proc has at least the test runner's parent in it
+        assert!(!list_processes(Path::new("/proc")).is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[async_std::test]
+    async fn count_and_limits_are_checked() {
+        let root = fake_proc("limits");
+        let ticks = clock_ticks_per_second() as u64;
+        fake_process(&root, 10, "worker", &["worker", "--queue=emails"], "workers.service", 100*1024, 0);
+        fake_process(&root, 11, "worker", &["worker", "--queue=reports"], "workers.service", 600*1024, 0);
+        fake_process(&root, 12, "worker", &["worker", "--queue=emails"], "other.service", 10*1024, 0);
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+
+        let mut by_unit = ProcessCheck::new(process_config(Some("^worker$"), None, Some("workers.service")), &root).unwrap();
+        by_unit.config.min_count = 3;
+        assert_eq!(check_process(&mut by_unit, Instant::now(), false, Some(&tx)).await, State::Critical);
+
+        let mut emails = ProcessCheck::new(process_config(None, Some("queue=emails"), None), &root).unwrap();
+        emails.config.min_count = 2;
+        emails.config.rss_mb = Thresholds { warn_above: Some(500.0), ..Thresholds::default() };
+        emails.config.cpu_percent = Thresholds { critical_above: Some(90.0), ..Thresholds::default() };
+        let start = Instant::now();
+        assert_eq!(check_process(&mut emails, start, false, Some(&tx)).await, State::Ok);
+        //pid 10 used a whole cpu for the 10 seconds between checks
+        fake_process(&root, 10, "worker", &["worker", "--queue=emails"], "workers.service", 100*1024, 10*ticks);
+        assert_eq!(check_process(&mut emails, start + Duration::from_secs(10), false, Some(&tx)).await, State::Critical);
+
+        let mut big = ProcessCheck::new(process_config(Some("^worker$"), None, None), &root).unwrap();
+        big.config.rss_mb = Thresholds { warn_above: Some(500.0), ..Thresholds::default() };
+        assert_eq!(check_process(&mut big, start, false, Some(&tx)).await, State::Warn);
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert_eq!(sent, vec![
+            "[process] workers is CRITICAL: 2 running, expected at least 3",
+            "[process] workers is OK: 2 running, largest is 100 MB",
+            "[process] workers is CRITICAL (was OK): 2 running, largest is 100 MB, busiest uses 100.0% cpu: pid 10 uses 100.0% cpu",
+            "[process] workers is WARN: 3 running, largest is 600 MB: pid 11 uses 600 MB",
+        ]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::VecDeque,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use toml::Value;
+use crate::alert::describe_duration;
+use crate::status::State;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Decrease,
+    Increase,
+    Change,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleKind {
+    Delta { above: Option<f64>, below: Option<f64> }, //how much the value moved over the window
+    Rate { above: Option<f64>, below: Option<f64> }, //how much the value moved per minute over the window
+    Stall { direction: Direction }, //the value didn't move in direction at all over the window
+}
+
+//a rule about how a value moves over time, e.g. "grew by more than 500 in 5 minutes"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub kind: RuleKind,
+    pub window: Duration,
+    pub severity: State,
+    pub column: Option<String>, //which column of a query result the rule looks at. None means the query's column
+}
+
+impl Rule {
+    //reads one entry of a rules array:
+    //{ kind = "delta"|"rate"|"stall", minutes = 5, above = 500, below = -10, direction = "decrease", severity = "warn"|"critical", column = "count" }
+    pub fn from_toml(what: &str, toml_value: &Value) -> Result<Rule,String> {
+        let number = |key: &str| -> Result<Option<f64>,String> {
+            match toml_value.get(key) {
+                None => Ok(None),
+                Some(Value::Integer(i)) => Ok(Some(*i as f64)),
+                Some(Value::Float(f)) => Ok(Some(*f)),
+                Some(v) => Err(format!("{} of a rule for {} is not a number:{}", key, what, v))
+            }
+        };
+
+        //inf or more minutes than a Duration holds would panic
+        let window = number("minutes")?
+            .filter(|m| *m > 0.0)
+            .and_then(|m| Duration::try_from_secs_f64(m*60.0).ok())
+            .ok_or(format!("rule for {} needs a positive number of minutes", what))?;
+
+        let severity = match toml_value.get("severity").map(|s| s.as_str()) {
+            None | Some(Some("warn")) => State::Warn,
+            Some(Some("critical")) => State::Critical,
+            Some(_) => return Err(format!("severity of a rule for {} has to be warn or critical", what))
+        };
+
+        let kind = match toml_value.get("kind").and_then(|k| k.as_str()) {
+            Some("delta") | Some("rate") => {
+                let above = number("above")?;
+                let below = number("below")?;
+                if above.is_none() && below.is_none() {
+                    return Err(format!("delta and rate rules for {} need above or below", what));
+                }
+                if toml_value.get("kind").and_then(|k| k.as_str()) == Some("delta") {
+                    RuleKind::Delta { above, below }
+                } else {
+                    RuleKind::Rate { above, below }
+                }
+            },
+            Some("stall") => {
+                let direction = match toml_value.get("direction").map(|d| d.as_str()) {
+                    None | Some(Some("decrease")) => Direction::Decrease,
+                    Some(Some("increase")) => Direction::Increase,
+                    Some(Some("change")) => Direction::Change,
+                    Some(_) => return Err(format!("direction of a stall rule for {} has to be decrease, increase or change", what))
+                };
+                RuleKind::Stall { direction }
+            },
+            _ => return Err(format!("rule for {} needs a kind of delta, rate or stall:{}", what, toml_value))
+        };
+
+        let column = match toml_value.get("column") {
+            None => None,
+            Some(c) => Some(c.as_str().ok_or(format!("column of a rule for {} is not a string", what))?.to_string())
+        };
+
+        Ok(Rule {
+            kind,
+            window,
+            severity,
+            column,
+        })
+    }
+
+    fn minutes(&self) -> f64 {
+        self.window.as_secs_f64()/60.0
+    }
+
+    //returns a description of what happened if the rule is broken
+    pub fn check(&self, history: &History, now: Instant) -> Option<String> {
+        let (latest_time, latest) = history.latest()?;
+        let (start_time, start) = history.at_window_start(now, self.window)?;
+        let moved = latest - start;
+        //how long the samples we compare cover. Less than the window until we've been running
+        //that long
+        let elapsed = latest_time.duration_since(start_time);
+
+        match &self.kind {
+            RuleKind::Delta { above, below } => {
+                if above.is_some_and(|a| moved > a) {
+                    Some(format!("grew by {} in {}", moved, describe_duration(elapsed)))
+                } else if below.is_some_and(|b| moved < b) {
+                    Some(format!("changed by {} in {}", moved, describe_duration(elapsed)))
+                } else {
+                    None
+                }
+            },
+            RuleKind::Rate { above, below } => {
+                let elapsed = elapsed.as_secs_f64()/60.0;
+                if elapsed <= 0.0 {
+                    return None;
+                }
+                let rate = moved/elapsed;
+                if above.is_some_and(|a| rate > a) || below.is_some_and(|b| rate < b) {
+                    Some(format!("is changing by {:.2} per minute", rate))
+                } else {
+                    None
+                }
+            },
+            RuleKind::Stall { direction } => {
+                //only judge a stall once we've watched the value for the whole window
+                if now.duration_since(start_time) < self.window {
+                    return None;
+                }
+                let values: Vec<f64> = history.since(start_time).collect();
+                let moved = values.windows(2).any(|w| match direction {
+                    Direction::Decrease => w[1] < w[0],
+                    Direction::Increase => w[1] > w[0],
+                    Direction::Change => w[1] != w[0],
+                });
+                if moved {
+                    None
+                } else {
+                    let what = match direction {
+                        Direction::Decrease => "decreased",
+                        Direction::Increase => "increased",
+                        Direction::Change => "changed",
+                    };
+                    Some(format!("hasn't {} in {} minutes", what, self.minutes()))
+                }
+            },
+        }
+    }
+}
+
+//time stamped values of a query, kept just long enough for the longest rule window
+#[derive(Default)]
+pub struct History {
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl History {
+    pub fn push(&mut self, now: Instant, value: f64, keep: Duration) {
+        self.samples.push_back((now, value));
+        //drop samples that are older than keep, but hold on to the newest one of those so
+        //there is always a value from the start of the window to compare against
+        while self.samples.len() > 1 && now.duration_since(self.samples[1].0) >= keep {
+            self.samples.pop_front();
+        }
+    }
+
+    fn latest(&self) -> Option<(Instant, f64)> {
+        self.samples.back().copied()
+    }
+
+    //the newest sample taken at or before the start of the window, or the oldest one we
+    //have if we haven't been running that long
+    fn at_window_start(&self, now: Instant, window: Duration) -> Option<(Instant, f64)> {
+        self.samples.iter()
+            .rev()
+            .find(|(t, _)| now.duration_since(*t) >= window)
+            .or_else(|| self.samples.front())
+            .copied()
+    }
+
+    fn since(&self, start: Instant) -> impl Iterator<Item = f64> + '_ {
+        self.samples.iter().filter(move |(t, _)| *t >= start).map(|(_, v)| *v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{History, Rule};
+    use crate::status::State;
+    use std::time::{Duration, Instant};
+
+    fn rule(s: &str) -> Rule {
+        Rule::from_toml("test", &toml::from_str(s).unwrap()).unwrap()
+    }
+
+    fn minutes(m: u64) -> Duration {
+        Duration::from_secs(m*60)
+    }
+
+    #[test]
+    fn delta_and_rate_rules() {
+        let delta = rule("kind = \"delta\"\nminutes = 5\nabove = 500\nseverity = \"critical\"");
+        let rate = rule("kind = \"rate\"\nminutes = 5\nabove = 50");
+        assert_eq!(delta.severity, State::Critical);
+        assert_eq!(rate.severity, State::Warn);
+
+        let start = Instant::now();
+        let mut history = History::default();
+        for (m, v) in [(0, 100.0), (1, 150.0), (2, 200.0), (5, 400.0)].iter() {
+            history.push(start + minutes(*m), *v, minutes(5));
+        }
+        let now = start + minutes(5);
+        assert_eq!(delta.check(&history, now), None);
+        assert_eq!(rate.check(&history, now), Some("is changing by 60.00 per minute".to_string()));
+
+        history.push(start + minutes(6), 700.0, minutes(5));
+        assert_eq!(delta.check(&history, start + minutes(6)), Some("grew by 550 in 5m".to_string()));
+    }
+
+    #[test]
+    fn stall_rule_needs_a_full_window() {
+        let stall = rule("kind = \"stall\"\nminutes = 30");
+        let start = Instant::now();
+        let mut history = History::default();
+        history.push(start, 10.0, minutes(30));
+        history.push(start + minutes(10), 12.0, minutes(30));
+        assert_eq!(stall.check(&history, start + minutes(10)), None);
+
+        history.push(start + minutes(30), 12.0, minutes(30));
+        assert_eq!(stall.check(&history, start + minutes(30)), Some("hasn't decreased in 30 minutes".to_string()));
+
+        history.push(start + minutes(40), 3.0, minutes(30));
+        assert_eq!(stall.check(&history, start + minutes(40)), None);
+    }
+
+    #[test]
+    fn bad_rules_are_rejected() {
+        let bad = |s: &str| Rule::from_toml("test", &toml::from_str(s).unwrap()).is_err();
+        assert!(bad("kind = \"delta\"\nminutes = 5"));
+        assert!(bad("kind = \"stall\""));
+        assert!(bad("kind = \"median\"\nminutes = 5"));
+        assert!(bad("kind = \"stall\"\nminutes = 5\nseverity = \"panic\""));
+        for minutes in ["inf", "nan", "1e300", "-5"].iter() {
+            let error = Rule::from_toml("test", &toml::from_str(&format!("kind = \"stall\"\nminutes = {}", minutes)).unwrap()).unwrap_err();
+            assert_eq!(error, "rule for test needs a positive number of minutes");
+        }
+    }
+}
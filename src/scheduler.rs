@@ -0,0 +1,86 @@
+use async_std::task;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+//the shortest interval a schedule runs on. A zero interval would never move the next run forward
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+//the timer of a single check. Every url, query and log file gets one of these so that each
+//runs at its own interval instead of waiting for the others to finish
+pub struct Schedule {
+    interval: Duration,
+    next: Instant,
+}
+
+impl Schedule {
+    pub fn new(interval: Duration) -> Schedule {
+        let interval = if interval.is_zero() {
+            log::warn!("A check has an interval of 0. It runs every {} second instead", MIN_INTERVAL.as_secs());
+            MIN_INTERVAL
+        } else {
+            interval
+        };
+        Schedule {
+            interval,
+            next: Instant::now(),
+        }
+    }
+
+    pub fn every_seconds(seconds: u64) -> Schedule {
+        Schedule::new(Duration::from_secs(seconds))
+    }
+
+    //waits until the check is due. The first call returns straight away. Runs are kept on
+    //the interval regardless of how long the check takes. When a check overruns, the late
+    //run happens straight away and any other missed runs are skipped rather than run back to back
+    pub async fn tick(&mut self) {
+        let now = Instant::now();
+        if self.next > now {
+            task::sleep(self.next - now).await;
+        }
+        self.next += self.interval;
+        let now = Instant::now();
+        while self.next <= now {
+            self.next += self.interval;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Schedule, MIN_INTERVAL};
+    use async_std::task;
+    use std::time::{Duration, Instant};
+
+    #[async_std::test]
+    async fn ticks_on_the_interval_and_skips_missed_runs() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(Duration::from_millis(50));
+        schedule.tick().await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        schedule.tick().await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        //the check took two and a half intervals. It runs once straight away and then goes
+        //back to the slots it would have had
+        task::sleep(Duration::from_millis(125)).await;
+        schedule.tick().await;
+        assert!(start.elapsed() < Duration::from_millis(200));
+        schedule.tick().await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_millis(250), "{:?}", elapsed);
+    }
+
+    #[async_std::test]
+    async fn zero_intervals_are_clamped() {
+        let mut schedule = Schedule::every_seconds(0);
+        assert_eq!(schedule.interval, MIN_INTERVAL);
+        let start = Instant::now();
+        schedule.tick().await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert!(schedule.next > start);
+    }
+}
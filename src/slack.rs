@@ -1,49 +1,356 @@
-use serde::{Deserialize, Serialize};
-use surf::http::status::StatusCode;
+use async_lock::Mutex;
+use async_std::future::timeout;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::{json, Value as Json};
 use std::{
-    sync::{
-        mpsc::{
-            Receiver,
-        }
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{
+        Duration,
+        Instant,
     },
 };
+use crate::alert::Alert;
+use crate::configuration::SlackConfig;
+use crate::notifier::{hostname, Notifier};
+use surf::http::status::StatusCode;
 
-#[derive(Deserialize, Serialize)]
+const SLACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize)]
 struct SlackPost {
-    text: String,
+    text: String, //what notifications and clients that can't show blocks fall back to
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<Attachment>,
 }
 
-pub async fn start_slack_poster(slack_url: String, slack_rx: Receiver<String>) -> Result<(),String> {
-    log::trace!("entering start_slack_poster");
-    log::debug!("this is the slack url:{}",slack_url);
+//blocks can only be coloured inside an attachment
+#[derive(Serialize)]
+struct Attachment {
+    color: &'static str,
+    blocks: Vec<Json>,
+}
 
-    for msg in slack_rx {
-        let data = SlackPost { 
-            text: msg.to_string(),
-        };
+//slack wants these escaped in mrkdwn
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+//cuts text down to the most slack takes in a block
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        format!("{}…", text.chars().take(max - 1).collect::<String>())
+    }
+}
+
+//the blocks of an alert: a header with the check and its state, the message with a field for
+//each detail the alert has, and a footer with where and when it happened
+fn alert_blocks(alert: &Alert, host: &str) -> Vec<Json> {
+    let fields: Vec<Json> = alert.details().iter()
+        .map(|(label, value)| json!({ "type": "mrkdwn", "text": truncate(&format!("*{}*\n{}", label, escape(value)), 2000) }))
+        .collect();
+
+    //slack shows the date in the reader's timezone and falls back to the text after the |
+    let footer = format!("yam on {} | <!date^{}^{{date_short_pretty}} {{time_secs}}|{}>",
+        escape(host), alert.timestamp.timestamp(), alert.timestamp.format("%Y-%m-%d %H:%M:%S UTC"));
+
+    vec![
+        json!({ "type": "header", "text": { "type": "plain_text", "text": truncate(&alert.headline(), 150) } }),
+        json!({ "type": "section", "text": { "type": "mrkdwn", "text": truncate(&escape(&alert.message), 3000) }, "fields": fields }),
+        json!({ "type": "context", "elements": [{ "type": "mrkdwn", "text": footer }] }),
+    ]
+}
+
+//why a post didn't go through
+enum PostError {
+    Retry(String, Option<Duration>), //worth trying again. After the wait slack asked for if it did
+    Permanent(String),
+}
+
+//an alert waiting to be posted
+struct Pending {
+    alert: Alert,
+    attempts: u32, //how many times posting it failed so far
+    next_attempt: Instant,
+}
+
+//posts alerts to a slack incoming webhook. Alerts that can't be posted because slack is down or
+//rate limiting are retried with exponential backoff and alerts behind them wait their turn
+pub struct SlackNotifier {
+    name: String,
+    config: SlackConfig,
+    host: String,
+    pending: Mutex<VecDeque<Pending>>,
+    dropped: AtomicU64,
+}
 
-        log::debug!("Trying to send this message to slack:{}",msg);
-        let res = surf::post(&slack_url).body_json(&data);
-        let res = match res {
-            Err(e) => {
-                log::error!("Could not construct slack url: {}",e.to_string());
-                continue
+impl SlackNotifier {
+    pub fn new(name: &str, config: &SlackConfig) -> SlackNotifier {
+        SlackNotifier {
+            name: name.to_string(),
+            config: config.clone(),
+            host: hostname(),
+            pending: Mutex::new(VecDeque::new()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    //how many alerts were given up on
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    async fn post(&self, alert: &Alert) -> Result<(),PostError> {
+        let data = SlackPost {
+            text: escape(&alert.to_string()),
+            attachments: if self.config.blocks {
+                vec![Attachment { color: alert.severity.color(), blocks: alert_blocks(alert, &self.host) }]
+            } else {
+                Vec::new()
             },
-            Ok(p) => p,
         };
-            
-        let res = res.await;
-        let res = match res {
-            Err(e) => {
-                log::error!("Could not connect to slack: {}",e.to_string());
-                continue
-            },
-            Ok(p) => p,
+
+        log::debug!("Trying to send this message to slack:{}",data.text);
+        let res = surf::post(&self.config.url).body_json(&data)
+            .map_err(|e| PostError::Permanent(format!("Could not construct slack url: {}",e)))?;
+        let res = match timeout(SLACK_TIMEOUT, res).await {
+            Err(_) => return Err(PostError::Retry(format!("slack didn't answer within {} seconds", SLACK_TIMEOUT.as_secs()), None)),
+            Ok(Err(e)) => return Err(PostError::Retry(format!("Could not connect to slack: {}",e), None)),
+            Ok(Ok(r)) => r
         };
 
-        if res.status() != StatusCode::OK {
-            log::warn!("slack call returned: {}",res.status().to_string());
+        let status = res.status();
+        let error = format!("slack call returned: {}",status);
+        if status.is_success() {
+            Ok(())
+        } else if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = res.header("Retry-After").and_then(|r| r.trim().parse().ok()).map(Duration::from_secs);
+            Err(PostError::Retry(error, retry_after))
+        } else if status.is_server_error() {
+            Err(PostError::Retry(error, None))
+        } else {
+            Err(PostError::Permanent(error))
+        }
+    }
+
+    //the wait before the next attempt after attempts failed ones. It doubles from backoff up to
+    //max_backoff and up to half of it is taken off at random so retries don't bunch up
+    fn backoff(&self, attempts: u32) -> Duration {
+        let doublings = 2u32.saturating_pow(attempts.saturating_sub(1));
+        let wait = self.config.backoff.saturating_mul(doublings).min(self.config.max_backoff);
+        wait.mul_f64(1.0 - fastrand::f64()/2.0)
+    }
+
+    fn give_up(&self, alert: &Alert, why: &str) {
+        let total = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        log::error!("Gave up sending the alert of {} to {}: {}. {} alerts dropped so far", alert.check_id, self.name, why, total);
+    }
+
+    //posts the waiting alerts in order until one of them fails or isn't due yet
+    async fn send_due(&self) {
+        let mut pending = self.pending.lock().await;
+        loop {
+            let mut next = match pending.pop_front() {
+                None => return,
+                Some(p) if p.next_attempt <= Instant::now() => p,
+                Some(p) => {
+                    pending.push_front(p);
+                    return;
+                }
+            };
+            match self.post(&next.alert).await {
+                Ok(()) => {},
+                Err(PostError::Permanent(e)) => self.give_up(&next.alert, &e),
+                Err(PostError::Retry(e, _)) if next.attempts >= self.config.retries => self.give_up(&next.alert, &e),
+                Err(PostError::Retry(e, retry_after)) => {
+                    next.attempts += 1;
+                    let wait = retry_after.unwrap_or_else(|| self.backoff(next.attempts));
+                    log::warn!("Could not send the alert of {} to {}: {}. Trying again in {:.1} seconds", next.alert.check_id, self.name, e, wait.as_secs_f64());
+                    next.next_attempt = Instant::now() + wait;
+                    pending.push_front(next);
+                    return;
+                }
+            }
         }
     }
-    Err("slack thread is exiting. This should never happen".to_string())
+}
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    //queues the alert behind any that are waiting to be retried. When the queue is full the
+    //oldest one is given up on
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(),String>> {
+        Box::pin(async move {
+            let mut pending = self.pending.lock().await;
+            if pending.len() >= self.config.retry_queue_size {
+                if let Some(oldest) = pending.pop_front() {
+                    self.give_up(&oldest.alert, "the retry queue is full");
+                }
+            }
+            pending.push_back(Pending {
+                alert: alert.clone(),
+                attempts: 0,
+                next_attempt: Instant::now(),
+            });
+            Ok(())
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(),String>> {
+        Box::pin(async move {
+            self.send_due().await;
+            Ok(())
+        })
+    }
+
+    fn undelivered(&self) -> BoxFuture<'_, Vec<Alert>> {
+        Box::pin(async move {
+            self.pending.lock().await.iter().map(|p| p.alert.clone()).collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SlackNotifier;
+    use crate::alert::{Alert, MonitorKind, Severity};
+    use crate::configuration::SlackConfig;
+    use crate::notifier::Notifier;
+    use crate::status::{State, Thresholds};
+    use crate::test_support::{http_response, http_stand_in, request_body};
+    use async_std::task;
+    use serde_json::Value as Json;
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    //a stand in for slack that answers with the statuses in script, and the last one forever
+    //after that. Returns its url and what was posted to it
+    fn slack_server(script: Vec<&'static str>) -> (String, Arc<Mutex<Vec<Json>>>) {
+        let posted = Arc::new(Mutex::new(Vec::new()));
+        let received = posted.clone();
+        let script: Mutex<VecDeque<&str>> = Mutex::new(script.into());
+        let url = http_stand_in("/services/T0/B0/x", Duration::from_millis(0), move |request| {
+            received.lock().unwrap().push(serde_json::from_str(&request_body(request)).unwrap());
+            let mut script = script.lock().unwrap();
+            let status = if script.len() > 1 { script.pop_front().unwrap() } else { script[0] };
+            http_response(status, "")
+        });
+        (url, posted)
+    }
+
+    fn slack_config(url: &str, retries: u32) -> SlackConfig {
+        let mut config = SlackConfig::new(url);
+        config.retries = retries;
+        config.backoff = Duration::from_millis(50);
+        config.max_backoff = Duration::from_millis(100);
+        config
+    }
+
+    fn alert(message: &str) -> Alert {
+        Alert::new(MonitorKind::Tcp, message, message.to_string())
+    }
+
+    //flushes until nothing is waiting any more
+    async fn flush_all(slack: &SlackNotifier) {
+        let start = Instant::now();
+        while !slack.pending.lock().await.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(10), "alerts are still waiting");
+            slack.flush().await.unwrap();
+            task::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[async_std::test]
+    async fn rate_limits_and_errors_are_retried() {
+        let (url, posted) = slack_server(vec!["429 Too Many Requests\r\nRetry-After: 1", "500 Internal Server Error", "200 OK"]);
+        let slack = SlackNotifier::new("slack", &slack_config(&url, 5));
+        let start = Instant::now();
+        slack.notify(&alert("first")).await.unwrap();
+        slack.notify(&alert("second")).await.unwrap();
+        flush_all(&slack).await;
+
+        assert!(start.elapsed() >= Duration::from_secs(1), "Retry-After wasn't waited for");
+        let texts: Vec<String> = posted.lock().unwrap().iter().map(|p| p["text"].as_str().unwrap().to_string()).collect();
+        assert_eq!(texts, vec!["first", "first", "first", "second"]);
+        assert_eq!(slack.dropped(), 0);
+    }
+
+    #[async_std::test]
+    async fn alerts_are_given_up_on() {
+        let (url, posted) = slack_server(vec!["503 Service Unavailable"]);
+        let slack = SlackNotifier::new("slack", &slack_config(&url, 2));
+        slack.notify(&alert("down")).await.unwrap();
+        flush_all(&slack).await;
+        assert_eq!(posted.lock().unwrap().len(), 3);
+        assert_eq!(slack.dropped(), 1);
+
+        //a bad request won't get any better
+        let (url, posted) = slack_server(vec!["400 Bad Request"]);
+        let slack = SlackNotifier::new("slack", &slack_config(&url, 2));
+        slack.notify(&alert("invalid")).await.unwrap();
+        flush_all(&slack).await;
+        assert_eq!(posted.lock().unwrap().len(), 1);
+        assert_eq!(slack.dropped(), 1);
+
+        let mut config = slack_config("http://127.0.0.1:1/", 2);
+        config.retry_queue_size = 2;
+        let slack = SlackNotifier::new("slack", &config);
+        for message in ["a", "b", "c"].iter() {
+            slack.notify(&alert(message)).await.unwrap();
+        }
+        assert_eq!(slack.dropped(), 1);
+        let waiting: Vec<String> = slack.pending.lock().await.iter().map(|p| p.alert.message.clone()).collect();
+        assert_eq!(waiting, vec!["b", "c"]);
+    }
+
+    #[async_std::test]
+    async fn alerts_are_posted_as_coloured_blocks() {
+        let (url, posted) = slack_server(vec!["200 OK"]);
+        let slack = SlackNotifier::new("slack", &slack_config(&url, 0));
+        let thresholds = Thresholds { warn_above: Some(80.0), critical_above: Some(90.0), ..Thresholds::default() };
+        let full = Alert::new(MonitorKind::System, "disk /", "[system] disk / is CRITICAL (was WARN): 95.0% used <& more>".to_string())
+            .with_state(State::Critical, Some(State::Warn))
+            .with_duration(Some(Duration::from_secs(3900)))
+            .with_value("95.00")
+            .with_threshold(&thresholds);
+        slack.notify(&full).await.unwrap();
+        flush_all(&slack).await;
+
+        let post = posted.lock().unwrap().pop().unwrap();
+        assert_eq!(post["text"], "[system] disk / is CRITICAL (was WARN): 95.0% used &lt;&amp; more&gt;");
+        let attachment = &post["attachments"][0];
+        assert_eq!(attachment["color"], "#d72b3f");
+        let blocks = attachment["blocks"].as_array().unwrap();
+        assert_eq!(blocks[0]["text"]["text"], "disk / is CRITICAL");
+        assert_eq!(blocks[1]["text"]["text"], "[system] disk / is CRITICAL (was WARN): 95.0% used &lt;&amp; more&gt;");
+        let fields: Vec<&str> = blocks[1]["fields"].as_array().unwrap().iter().map(|f| f["text"].as_str().unwrap()).collect();
+        assert_eq!(fields, vec!["*Monitor*\nsystem", "*Severity*\ncritical", "*Value*\n95.00", "*Threshold*\nwarn_above 80, critical_above 90", "*Was WARN for*\n1h 5m"]);
+        let footer = blocks[2]["elements"][0]["text"].as_str().unwrap();
+        assert!(footer.starts_with("yam on ") && footer.contains(&format!("<!date^{}^", full.timestamp.timestamp())), "{}", footer);
+
+        //a log line has no state so it only gets what it has
+        slack.notify(&Alert::new(MonitorKind::Log, "app", "app:ERROR".to_string()).with_severity(Severity::Warning)).await.unwrap();
+        flush_all(&slack).await;
+        let post = posted.lock().unwrap().pop().unwrap();
+        assert_eq!(post["attachments"][0]["color"], "#daa038");
+        assert_eq!(post["attachments"][0]["blocks"][0]["text"]["text"], "app");
+        assert_eq!(post["attachments"][0]["blocks"][1]["fields"].as_array().unwrap().len(), 2);
+
+        let mut config = slack_config(&url, 0);
+        config.blocks = false;
+        let slack = SlackNotifier::new("slack", &config);
+        slack.notify(&full).await.unwrap();
+        flush_all(&slack).await;
+        let post = posted.lock().unwrap().pop().unwrap();
+        assert_eq!(post, serde_json::json!({ "text": "[system] disk / is CRITICAL (was WARN): 95.0% used &lt;&amp; more&gt;" }));
+    }
 }
@@ -0,0 +1,183 @@
+use std::{
+    fmt,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use toml::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    Ok,
+    Warn,
+    Critical,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Ok => write!(f, "OK"),
+            State::Warn => write!(f, "WARN"),
+            State::Critical => write!(f, "CRITICAL"),
+        }
+    }
+}
+
+//limits a value is checked against. min and max are hard limits so going outside them is critical
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Thresholds {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub warn_above: Option<f64>,
+    pub critical_above: Option<f64>,
+    pub warn_below: Option<f64>,
+    pub critical_below: Option<f64>,
+}
+
+impl Thresholds {
+    //reads the threshold keys out of a toml table. Keys that aren't there are left unset
+    pub fn from_toml(what: &str, toml_value: &Value) -> Result<Thresholds,String> {
+        let get = |key: &str| -> Result<Option<f64>,String> {
+            match toml_value.get(key) {
+                None => Ok(None),
+                Some(Value::Integer(i)) => Ok(Some(*i as f64)),
+                Some(Value::Float(f)) => Ok(Some(*f)),
+                Some(v) => Err(format!("{} of {} is not a number:{}", key, what, v))
+            }
+        };
+        Ok(Thresholds {
+            min: get("min")?,
+            max: get("max")?,
+            warn_above: get("warn_above")?,
+            critical_above: get("critical_above")?,
+            warn_below: get("warn_below")?,
+            critical_below: get("critical_below")?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Thresholds::default()
+    }
+
+    pub fn state(&self, value: f64) -> State {
+        let above = |limit: Option<f64>| limit.is_some_and(|l| value > l);
+        let below = |limit: Option<f64>| limit.is_some_and(|l| value < l);
+
+        if above(self.max) || above(self.critical_above) || below(self.min) || below(self.critical_below) {
+            State::Critical
+        } else if above(self.warn_above) || below(self.warn_below) {
+            State::Warn
+        } else {
+            State::Ok
+        }
+    }
+}
+
+//the limits that are set, e.g. "warn_above 80, critical_above 90"
+impl fmt::Display for Thresholds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limits = [
+            ("min", self.min),
+            ("critical_below", self.critical_below),
+            ("warn_below", self.warn_below),
+            ("warn_above", self.warn_above),
+            ("critical_above", self.critical_above),
+            ("max", self.max),
+        ];
+        let set: Vec<String> = limits.iter()
+            .filter_map(|(key, limit)| limit.map(|l| format!("{} {}", key, l)))
+            .collect();
+        write!(f, "{}", set.join(", "))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transition {
+    pub from: Option<State>, //None the first time a state is recorded
+    pub to: State,
+}
+
+//remembers the last state of a check so that notifications are only sent when it changes
+#[derive(Default)]
+pub struct StateTracker {
+    current: Option<State>,
+    since: Option<Instant>, //when the check went into the current state
+}
+
+impl StateTracker {
+    pub fn current(&self) -> Option<State> {
+        self.current
+    }
+
+    //how long the check has been in the current state
+    pub fn in_state_for(&self) -> Option<Duration> {
+        self.since.map(|s| s.elapsed())
+    }
+
+    //records the latest state and returns the transition if it differs from the last one
+    pub fn update(&mut self, state: State) -> Option<Transition> {
+        let from = self.current.replace(state);
+        if from == Some(state) {
+            None
+        } else {
+            self.since = Some(Instant::now());
+            Some(Transition { from, to: state })
+        }
+    }
+}
+
+//tells a monitor when it's time to resend its status even though nothing has changed
+pub struct ResendTimer {
+    every: Duration,
+    last: Instant,
+}
+
+impl ResendTimer {
+    pub fn new(resend_status_minutes: u64) -> ResendTimer {
+        ResendTimer {
+            every: Duration::from_secs(resend_status_minutes*60),
+            last: Instant::now(),
+        }
+    }
+
+    pub fn due(&mut self) -> bool {
+        if self.last.elapsed() >= self.every {
+            self.last = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{State, StateTracker, Thresholds, Transition};
+    use std::time::Duration;
+
+    #[test]
+    fn thresholds_pick_the_worst_state() {
+        let t: toml::Value = toml::from_str("warn_above = 10\ncritical_above = 20.5\nmin = 0").unwrap();
+        let t = Thresholds::from_toml("test", &t).unwrap();
+        assert_eq!(t.state(5.0), State::Ok);
+        assert_eq!(t.state(10.0), State::Ok);
+        assert_eq!(t.state(11.0), State::Warn);
+        assert_eq!(t.state(21.0), State::Critical);
+        assert_eq!(t.state(-1.0), State::Critical);
+        assert!(Thresholds::default().is_empty());
+        assert_eq!(Thresholds::default().state(1e9), State::Ok);
+        assert_eq!(t.to_string(), "min 0, warn_above 10, critical_above 20.5");
+    }
+
+    #[test]
+    fn tracker_reports_only_changes() {
+        let mut tracker = StateTracker::default();
+        assert_eq!(tracker.in_state_for(), None);
+        assert_eq!(tracker.update(State::Ok), Some(Transition { from: None, to: State::Ok }));
+        assert_eq!(tracker.update(State::Ok), None);
+        assert_eq!(tracker.update(State::Warn), Some(Transition { from: Some(State::Ok), to: State::Warn }));
+        assert_eq!(tracker.current(), Some(State::Warn));
+        assert!(tracker.in_state_for().unwrap() < Duration::from_secs(1));
+    }
+}
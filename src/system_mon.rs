@@ -0,0 +1,297 @@
+use async_std::{future::timeout, task::{self, JoinHandle}};
+use futures::future::join_all;
+use std::{
+        ffi::CString,
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+};
+use crate::alert::{Alert, AlertSender, MonitorKind};
+use crate::configuration::{Configuration, SystemConfig};
+use crate::scheduler::Schedule;
+use crate::status::{ResendTimer, State, StateTracker, Thresholds, Transition};
+
+const GB: f64 = 1024.0*1024.0*1024.0;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Resource {
+    Disk(String),
+    Memory,
+    Swap,
+    Load(u8),
+}
+
+impl Resource {
+    fn name(&self) -> String {
+        match self {
+            Resource::Disk(mount) => format!("disk {}", mount),
+            Resource::Memory => "memory".to_string(),
+            Resource::Swap => "swap".to_string(),
+            Resource::Load(minutes) => format!("{} minute load average", minutes),
+        }
+    }
+
+    //returns the value the thresholds are checked against and a description of it
+    fn measure(&self, proc_root: &Path) -> Result<(f64, String),String> {
+        match self {
+            Resource::Disk(mount) => {
+                let (used, available) = disk_usage(mount)?;
+                Ok((used, format!("{:.1}% used, {:.1} GB free", used, available/GB)))
+            },
+            Resource::Memory => {
+                let values = meminfo(proc_root)?;
+                let total = meminfo_value(&values, "MemTotal")?;
+                let available = meminfo_value(&values, "MemAvailable")?;
+                let percent = if total > 0.0 { available/total*100.0 } else { 0.0 };
+                Ok((percent, format!("{:.1}% available, {:.1} of {:.1} GB", percent, available/GB, total/GB)))
+            },
+            Resource::Swap => {
+                let values = meminfo(proc_root)?;
+                let total = meminfo_value(&values, "SwapTotal")?;
+                let used = total - meminfo_value(&values, "SwapFree")?;
+                let percent = if total > 0.0 { used/total*100.0 } else { 0.0 };
+                Ok((percent, format!("{:.1}% used, {:.1} of {:.1} GB", percent, used/GB, total/GB)))
+            },
+            Resource::Load(minutes) => {
+                let loadavg = fs::read_to_string(proc_root.join("loadavg")).map_err(|e| format!("Could not read loadavg: {}", e))?;
+                let field = match minutes { 1 => 0, 5 => 1, _ => 2 };
+                let load: f64 = loadavg.split_whitespace().nth(field)
+                    .and_then(|l| l.parse().ok())
+                    .ok_or(format!("Could not read the load average out of {:?}", loadavg))?;
+                Ok((load, format!("{:.2}", load)))
+            },
+        }
+    }
+}
+
+//percent used and free bytes of the filesystem mount is on
+fn disk_usage(mount: &str) -> Result<(f64, f64),String> {
+    let path = CString::new(mount).map_err(|e| e.to_string())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    let block = stat.f_frsize as f64;
+    let total = stat.f_blocks as f64*block;
+    let free = stat.f_bfree as f64*block;
+    let available = stat.f_bavail as f64*block; //what's left for users other than root
+    //used the same way df works it out, against what root can't use either
+    let used = total - free;
+    if used + available <= 0.0 {
+        return Err(format!("{} has no size", mount));
+    }
+    Ok((used/(used + available)*100.0, available))
+}
+
+//the values of /proc/meminfo in bytes
+fn meminfo(proc_root: &Path) -> Result<Vec<(String, f64)>,String> {
+    let meminfo = fs::read_to_string(proc_root.join("meminfo")).map_err(|e| format!("Could not read meminfo: {}", e))?;
+    Ok(meminfo.lines().filter_map(|l| {
+        let (key, value) = l.split_once(':')?;
+        let kb: f64 = value.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some((key.to_string(), kb*1024.0))
+    }).collect())
+}
+
+fn meminfo_value(values: &[(String, f64)], key: &str) -> Result<f64,String> {
+    values.iter().find(|(k, _)| k == key).map(|(_, v)| *v).ok_or(format!("meminfo has no {}", key))
+}
+
+struct ResourceCheck {
+    resource: Resource,
+    thresholds: Thresholds,
+    state: StateTracker,
+    running: Option<JoinHandle<Result<(f64, String),String>>>, //a measurement that timed out
+}
+
+impl ResourceCheck {
+    fn new(resource: Resource, thresholds: &Thresholds) -> ResourceCheck {
+        ResourceCheck {
+            resource,
+            thresholds: thresholds.clone(),
+            state: StateTracker::default(),
+            running: None,
+        }
+    }
+
+    //measures the resource on a blocking thread, because statvfs can hang on a dead network
+    //mount, and gives up waiting after time_limit. A measurement that's still running is waited
+    //on again by the next check instead of starting another one
+    async fn measure(&mut self, proc_root: &Path, time_limit: Duration) -> Result<(f64, String),String> {
+        let mut running = match self.running.take() {
+            Some(r) => r,
+            None => {
+                let resource = self.resource.clone();
+                let root = proc_root.to_path_buf();
+                task::spawn_blocking(move || resource.measure(&root))
+            }
+        };
+        match timeout(time_limit, &mut running).await {
+            Ok(measured) => measured,
+            Err(_) => {
+                self.running = Some(running);
+                Err(format!("timed out after {} seconds", time_limit.as_secs_f64()))
+            }
+        }
+    }
+}
+
+//the resources configured in [system], in the order they're checked
+fn resource_checks(system: &SystemConfig) -> Vec<ResourceCheck> {
+    let mut checks: Vec<ResourceCheck> = system.disks.iter()
+        .map(|d| ResourceCheck::new(Resource::Disk(d.mount.clone()), &d.used_percent))
+        .collect();
+    if let Some(t) = &system.memory_available_percent {
+        checks.push(ResourceCheck::new(Resource::Memory, t));
+    }
+    if let Some(t) = &system.swap_used_percent {
+        checks.push(ResourceCheck::new(Resource::Swap, t));
+    }
+    if let Some((minutes, t)) = &system.load {
+        checks.push(ResourceCheck::new(Resource::Load(*minutes), t));
+    }
+    checks
+}
+
+//measures one resource and sends an alert when its state changes or it's time to resend it.
+//A resource that can't be measured within time_limit is critical
+async fn check_resource(check: &mut ResourceCheck, proc_root: &Path, time_limit: Duration, resend: bool, alert_tx: Option<&AlertSender>) -> State {
+    let (state, description, value) = match check.measure(proc_root, time_limit).await {
+        Err(e) => (State::Critical, format!("could not be checked. {}", e), None),
+        Ok((value, description)) => (check.thresholds.state(value), description, Some(value)),
+    };
+    let name = check.resource.name();
+    match state {
+        State::Ok => log::info!("{} {} {}", name, description, state),
+        State::Warn => log::warn!("{} {} {}", name, description, state),
+        State::Critical => log::error!("{} {} {}", name, description, state),
+    }
+
+    let previous = check.state.current();
+    let duration = check.state.in_state_for();
+    let msg = match check.state.update(state) {
+        Some(Transition { from: Some(from), .. }) => format!("[system] {} is {} (was {}): {}", name, state, from, description),
+        Some(_) => format!("[system] {} is {}: {}", name, state, description),
+        None if resend => format!("[system] {} is still {}: {}", name, state, description),
+        None => return state
+    };
+
+    if let Some(tx) = alert_tx {
+        let mut alert = Alert::new(MonitorKind::System, &name, msg).with_state(state, previous).with_duration(duration);
+        if let Some(v) = value {
+            alert = alert.with_value(format!("{:.2}", v));
+        }
+        if !check.thresholds.is_empty() {
+            alert = alert.with_threshold(&check.thresholds);
+        }
+        if let Err(e) = tx.send(alert) {
+            log::error!("Could not queue the alert:{}",e);
+        }
+    }
+    state
+}
+
+//checks every configured resource of the machine yam runs on, all on the interval of [system]
+pub async fn system_mon_start(config: Arc<Configuration>, alert_tx: AlertSender) -> Result<(),String> {
+    let system = match &config.system {
+        None => return Err("System resource monitoring is not configured".to_string()),
+        Some(s) => s
+    };
+
+    let mut checks = resource_checks(system);
+    let mut schedule = Schedule::every_seconds(system.interval);
+    let mut resend_timer = ResendTimer::new(config.resend_status_minutes);
+    let alert_tx = if config.is_notify_configured() { Some(&alert_tx) } else { None };
+    let proc_root = PathBuf::from("/proc");
+    let time_limit = Duration::from_secs(system.interval);
+
+    loop {
+        schedule.tick().await;
+        let resend = resend_timer.due();
+        if resend {
+            log::info!("system_mon is resending the status of the system resources");
+        }
+        join_all(checks.iter_mut().map(|c| check_resource(c, &proc_root, time_limit, resend, alert_tx))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_resource, disk_usage, Resource, ResourceCheck};
+    use crate::alert::{alert_channel, QueueFull};
+    use crate::status::{State, Thresholds};
+    use std::{fs, path::Path, time::Duration};
+
+    const LIMIT: Duration = Duration::from_secs(5);
+
+    #[async_std::test]
+    async fn resources_are_measured_and_reported_on_change() {
+        let root = std::env::temp_dir().join(format!("yam_system_mon_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("meminfo"), "MemTotal:        8388608 kB\nMemFree:          100000 kB\nMemAvailable:     419430 kB\nSwapTotal:       2097152 kB\nSwapFree:        1048576 kB\n").unwrap();
+        fs::write(root.join("loadavg"), "0.50 4.25 3.00 2/345 6789\n").unwrap();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+
+        let mut memory = ResourceCheck::new(Resource::Memory, &Thresholds { warn_below: Some(10.0), critical_below: Some(5.0), ..Thresholds::default() });
+        assert_eq!(check_resource(&mut memory, &root, LIMIT, false, Some(&tx)).await, State::Critical);
+        assert_eq!(check_resource(&mut memory, &root, LIMIT, false, Some(&tx)).await, State::Critical);
+        assert_eq!(check_resource(&mut memory, &root, LIMIT, true, Some(&tx)).await, State::Critical);
+        let mut swap = ResourceCheck::new(Resource::Swap, &Thresholds { warn_above: Some(40.0), ..Thresholds::default() });
+        assert_eq!(check_resource(&mut swap, &root, LIMIT, false, Some(&tx)).await, State::Warn);
+        let mut load = ResourceCheck::new(Resource::Load(5), &Thresholds { warn_above: Some(4.0), ..Thresholds::default() });
+        assert_eq!(check_resource(&mut load, &root, LIMIT, false, Some(&tx)).await, State::Warn);
+        fs::write(root.join("loadavg"), "0.50 1.25 3.00 2/345 6789\n").unwrap();
+        assert_eq!(check_resource(&mut load, &root, LIMIT, false, Some(&tx)).await, State::Ok);
+        fs::remove_file(root.join("meminfo")).unwrap();
+        assert_eq!(check_resource(&mut swap, &root, LIMIT, false, Some(&tx)).await, State::Critical);
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert_eq!(sent[0], "[system] memory is CRITICAL: 5.0% available, 0.4 of 8.0 GB");
+        assert_eq!(sent[1], "[system] memory is still CRITICAL: 5.0% available, 0.4 of 8.0 GB");
+        assert_eq!(sent[2], "[system] swap is WARN: 50.0% used, 1.0 of 2.0 GB");
+        assert_eq!(sent[3], "[system] 5 minute load average is WARN: 4.25");
+        assert_eq!(sent[4], "[system] 5 minute load average is OK (was WARN): 1.25");
+        assert!(sent[5].starts_with("[system] swap is CRITICAL (was WARN): could not be checked. Could not read meminfo"), "{}", sent[5]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[async_std::test]
+    async fn disks_are_measured_with_statvfs() {
+        let (used, free) = disk_usage("/").unwrap();
+        assert!((0.0..=100.0).contains(&used) && free >= 0.0);
+        assert!(disk_usage("/no/such/mount").is_err());
+
+        let mut disk = ResourceCheck::new(Resource::Disk("/".to_string()), &Thresholds { critical_above: Some(100.0), ..Thresholds::default() });
+        assert_eq!(check_resource(&mut disk, Path::new("/proc"), LIMIT, false, None).await, State::Ok);
+        disk.thresholds = Thresholds { warn_below: Some(101.0), ..Thresholds::default() };
+        assert_eq!(check_resource(&mut disk, Path::new("/proc"), LIMIT, false, None).await, State::Warn);
+    }
+
+    #[async_std::test]
+    async fn measurements_that_hang_time_out() {
+        let root = std::env::temp_dir().join(format!("yam_system_mon_hang_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        //reading a fifo blocks until something writes to it, like statvfs on a dead mount
+        let fifo = root.join("loadavg");
+        let path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let limit = Duration::from_millis(200);
+
+        let mut load = ResourceCheck::new(Resource::Load(1), &Thresholds { warn_above: Some(4.0), ..Thresholds::default() });
+        assert_eq!(check_resource(&mut load, &root, limit, false, Some(&tx)).await, State::Critical);
+        assert_eq!(check_resource(&mut load, &root, limit, false, Some(&tx)).await, State::Critical);
+        assert!(load.running.is_some());
+        fs::write(&fifo, "0.50 4.25 3.00 2/345 6789\n").unwrap();
+        assert_eq!(check_resource(&mut load, &root, limit, false, Some(&tx)).await, State::Ok);
+        assert!(load.running.is_none());
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert_eq!(sent, vec![
+            "[system] 1 minute load average is CRITICAL: could not be checked. timed out after 0.2 seconds",
+            "[system] 1 minute load average is OK (was CRITICAL): 0.50",
+        ]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+use async_std::{
+    future::timeout,
+    io::{ReadExt, WriteExt},
+    net::TcpStream,
+};
+use futures::future::join_all;
+use regex::Regex;
+use std::{
+        sync::Arc,
+        time::Duration,
+};
+use crate::alert::{Alert, AlertSender, MonitorKind};
+use crate::configuration::{Configuration, TcpConfig};
+use crate::scheduler::Schedule;
+use crate::status::{ResendTimer, State, StateTracker, Transition};
+
+//stop reading a banner that never matches after this much
+const MAX_RESPONSE_BYTES: usize = 64*1024;
+
+struct TcpCheck {
+    config: TcpConfig,
+    expect: Option<Regex>,
+    state: StateTracker,
+}
+
+impl TcpCheck {
+    fn new(config: TcpConfig) -> Result<TcpCheck,String> {
+        let expect = match &config.expect {
+            None => None,
+            Some(r) => Some(Regex::new(r).map_err(|e| format!("Invalid expect regex for tcp check {}: {}", config.name, e))?),
+        };
+        Ok(TcpCheck {
+            config,
+            expect,
+            state: StateTracker::default(),
+        })
+    }
+
+    //connects, sends the payload and reads until the response matches or the connection closes.
+    //What was read so far is left in response
+    async fn talk(&self, response: &mut Vec<u8>) -> Result<(),String> {
+        let mut stream = TcpStream::connect((self.config.host.as_str(), self.config.port)).await
+            .map_err(|e| format!("Could not connect. {}", e))?;
+        if let Some(payload) = &self.config.send {
+            stream.write_all(payload.as_bytes()).await.map_err(|e| format!("Could not send. {}", e))?;
+        }
+        let expect = match &self.expect {
+            None => return Ok(()),
+            Some(r) => r
+        };
+
+        let mut buf = [0; 4096];
+        while !expect.is_match(&String::from_utf8_lossy(response)) && response.len() < MAX_RESPONSE_BYTES {
+            let read = stream.read(&mut buf).await.map_err(|e| format!("Could not read. {}", e))?;
+            if read == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..read]);
+        }
+        Ok(())
+    }
+
+    //returns the state of the service and a description of it
+    async fn probe(&self) -> (State, String) {
+        let time_limit = Duration::from_secs(self.config.timeout);
+        let mut response = Vec::new();
+        let result = timeout(time_limit, self.talk(&mut response)).await;
+        let response = String::from_utf8_lossy(&response);
+        let response = response.trim_end();
+
+        match (result, &self.expect) {
+            (Err(_), _) if response.is_empty() => (State::Critical, format!("timed out after {} seconds", time_limit.as_secs())),
+            (Err(_), _) => (State::Critical, format!("timed out after {} seconds. Got {:?}", time_limit.as_secs(), response)),
+            (Ok(Err(e)), _) => (State::Critical, e),
+            (Ok(Ok(())), None) => (State::Ok, "is accepting connections".to_string()),
+            (Ok(Ok(())), Some(r)) if r.is_match(response) => (State::Ok, format!("answered {:?}", response)),
+            (Ok(Ok(())), Some(r)) => (State::Critical, format!("answered {:?} which doesn't match {}", response, r)),
+        }
+    }
+}
+
+//probes the service once and sends an alert when its state changes or it's time to resend it
+async fn check_tcp(tcp: &mut TcpCheck, resend: bool, alert_tx: Option<&AlertSender>) -> State {
+    let (state, description) = tcp.probe().await;
+    let name = &tcp.config.name;
+    match state {
+        State::Ok => log::info!("tcp check {} {} {}", name, description, state),
+        _ => log::error!("tcp check {} {} {}", name, description, state),
+    }
+
+    let previous = tcp.state.current();
+    let duration = tcp.state.in_state_for();
+    let msg = match tcp.state.update(state) {
+        Some(Transition { from: Some(from), .. }) => format!("[tcp] {} is {} (was {}): {}", name, state, from, description),
+        Some(_) => format!("[tcp] {} is {}: {}", name, state, description),
+        None if resend => format!("[tcp] {} is still {}: {}", name, state, description),
+        None => return state
+    };
+
+    if let Some(tx) = alert_tx {
+        if let Err(e) = tx.send(Alert::new(MonitorKind::Tcp, name, msg).with_state(state, previous).with_duration(duration)) {
+            log::error!("Could not queue the alert:{}",e);
+        }
+    }
+    state
+}
+
+//checks one service on its own interval forever
+async fn watch_tcp(config: &Configuration, mut tcp: TcpCheck, alert_tx: AlertSender) {
+    let mut schedule = Schedule::every_seconds(tcp.config.interval);
+    let mut resend_timer = ResendTimer::new(config.resend_status_minutes);
+    let alert_tx = if config.is_notify_configured() { Some(&alert_tx) } else { None };
+
+    loop {
+        schedule.tick().await;
+        let resend = resend_timer.due();
+        if resend {
+            log::info!("tcp_mon is resending the status of {}", tcp.config.name);
+        }
+        check_tcp(&mut tcp, resend, alert_tx).await;
+    }
+}
+
+pub async fn tcp_mon_start(config: Arc<Configuration>, alert_tx: AlertSender) -> Result<(),String> {
+    let tcp_checks = match &config.tcp_checks {
+        None => return Err("TCP monitoring is not configured".to_string()),
+        Some(t) => t
+    };
+
+    let mut watches = Vec::new();
+    for tcp in tcp_checks.iter() {
+        watches.push(watch_tcp(&config, TcpCheck::new(tcp.clone())?, alert_tx.clone()));
+    }
+    join_all(watches).await;
+    Err("tcp_mon is exiting. This should never happen".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_tcp, TcpCheck};
+    use crate::alert::{alert_channel, Alert, QueueFull};
+    use crate::configuration::TcpConfig;
+    use crate::status::State;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    //a stand in for a redis server that answers PING with +PONG and anything else with an error
+    fn redis_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut line = String::new();
+                let _ = BufReader::new(stream.try_clone().unwrap()).read_line(&mut line);
+                let answer = if line.trim_end() == "PING" { "+PONG\r\n" } else { "-ERR unknown command\r\n" };
+                let _ = stream.write_all(answer.as_bytes());
+            }
+        });
+        port
+    }
+
+    //a server that accepts connections and never says anything
+    fn silent_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut open = Vec::new();
+            for stream in listener.incoming() {
+                open.push(stream);
+            }
+        });
+        port
+    }
+
+    fn tcp_config(port: u16, send: Option<&str>, expect: Option<&str>) -> TcpConfig {
+        TcpConfig {
+            name: "redis".to_string(),
+            host: "127.0.0.1".to_string(),
+            port,
+            send: send.map(|s| s.to_string()),
+            expect: expect.map(|s| s.to_string()),
+            interval: 60,
+            timeout: 1,
+        }
+    }
+
+    #[async_std::test]
+    async fn banners_are_matched() {
+        let port = redis_server();
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+
+        let mut ping = TcpCheck::new(tcp_config(port, Some("PING\r\n"), Some("^\\+PONG"))).unwrap();
+        assert_eq!(check_tcp(&mut ping, false, Some(&tx)).await, State::Ok);
+        assert_eq!(check_tcp(&mut ping, false, Some(&tx)).await, State::Ok);
+        let mut wrong = TcpCheck::new(tcp_config(port, Some("PONG\r\n"), Some("^\\+PONG"))).unwrap();
+        assert_eq!(check_tcp(&mut wrong, false, Some(&tx)).await, State::Critical);
+        let mut open = TcpCheck::new(tcp_config(port, None, None)).unwrap();
+        assert_eq!(check_tcp(&mut open, false, Some(&tx)).await, State::Ok);
+
+        let alerts: Vec<Alert> = rx.try_iter().collect();
+        assert_eq!((alerts[1].check_id.as_str(), alerts[1].state, alerts[1].previous_state), ("tcp/redis", Some(State::Critical), None));
+        let sent: Vec<String> = alerts.into_iter().map(|a| a.message).collect();
+        assert_eq!(sent, vec![
+            "[tcp] redis is OK: answered \"+PONG\"",
+            "[tcp] redis is CRITICAL: answered \"-ERR unknown command\" which doesn't match ^\\+PONG",
+            "[tcp] redis is OK: is accepting connections",
+        ]);
+        assert!(TcpCheck::new(tcp_config(port, None, Some("("))).is_err());
+    }
+
+    #[async_std::test]
+    async fn silence_and_refusals_are_critical() {
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut silent = TcpCheck::new(tcp_config(silent_server(), None, Some("^220"))).unwrap();
+        let start = std::time::Instant::now();
+        assert_eq!(check_tcp(&mut silent, false, Some(&tx)).await, State::Critical);
+        assert!(start.elapsed() < Duration::from_secs(3));
+        let mut closed = TcpCheck::new(tcp_config(1, None, None)).unwrap();
+        assert_eq!(check_tcp(&mut closed, false, Some(&tx)).await, State::Critical);
+
+        let sent: Vec<String> = rx.try_iter().map(|a| a.message).collect();
+        assert_eq!(sent[0], "[tcp] redis is CRITICAL: timed out after 1 seconds");
+        assert!(sent[1].starts_with("[tcp] redis is CRITICAL: Could not connect."), "{}", sent[1]);
+    }
+}
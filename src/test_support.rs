@@ -0,0 +1,125 @@
+//stand ins shared by the tests of several modules
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use openssl::{
+    asn1::Asn1Time,
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::PKey,
+    ssl::{SslAcceptor, SslMethod},
+    x509::{extension::SubjectAlternativeName, X509Builder, X509NameBuilder},
+};
+
+//an acceptor for localhost with a self signed certificate that expires in days. The certificate
+//is written to a file named after prefix and port so a client can trust it. Returns the acceptor
+//and the path of the file, which the test removes when it's done
+pub fn localhost_tls(days: u32, prefix: &str, port: u16) -> (SslAcceptor, String) {
+    let key = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_nid(Nid::COMMONNAME, "localhost").unwrap();
+    let name = name.build();
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(days).unwrap()).unwrap();
+    let san = SubjectAlternativeName::new().dns("localhost").build(&builder.x509v3_context(None, None)).unwrap();
+    builder.append_extension(san).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    let cert = builder.build();
+
+    let path = std::env::temp_dir().join(format!("{}_{}_{}.pem", prefix, std::process::id(), port));
+    std::fs::write(&path, cert.to_pem().unwrap()).unwrap();
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor.set_private_key(&key).unwrap();
+    acceptor.set_certificate(&cert).unwrap();
+    (acceptor.build(), path.to_str().unwrap().to_string())
+}
+
+//reads one request, headers and body. curl sends bodies chunked after asking for a 100 Continue
+pub fn read_request(stream: &mut TcpStream) -> String {
+    stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let mut request = Vec::new();
+    let mut buf = [0; 4096];
+    let mut continued = false;
+    loop {
+        let n = stream.read(&mut buf).unwrap_or(0);
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request).to_string();
+        let lower = text.to_lowercase();
+        let end = match text.find("\r\n\r\n") {
+            Some(end) if n > 0 => end + 4,
+            _ => return text
+        };
+        if lower.contains("expect: 100-continue") && !continued {
+            let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+            continued = true;
+        }
+        let complete = if lower.contains("transfer-encoding: chunked") {
+            text[end..].ends_with("0\r\n\r\n")
+        } else {
+            let length = lower.lines()
+                .find_map(|l| l.strip_prefix("content-length:").map(|v| v.trim().parse().unwrap_or(0)))
+                .unwrap_or(0);
+            request.len() >= end + length
+        };
+        if complete {
+            return text;
+        }
+    }
+}
+
+//the body of a request from read_request, with the chunks joined back together
+pub fn request_body(request: &str) -> String {
+    let (head, body) = request.split_once("\r\n\r\n").unwrap_or((request, ""));
+    if !head.to_lowercase().contains("transfer-encoding: chunked") {
+        return body.to_string();
+    }
+    let mut joined = Vec::new();
+    let mut rest = body.as_bytes();
+    while let Some(line) = rest.windows(2).position(|w| w == b"\r\n") {
+        let size = usize::from_str_radix(String::from_utf8_lossy(&rest[..line]).trim(), 16).unwrap_or(0);
+        if size == 0 || rest.len() < line + 2 + size {
+            break;
+        }
+        joined.extend_from_slice(&rest[line + 2..line + 2 + size]);
+        rest = &rest[(line + 4 + size).min(rest.len())..];
+    }
+    String::from_utf8_lossy(&joined).to_string()
+}
+
+//a stand in http server that answers every request with whatever respond returns for it after
+//waiting delay. Returns the url of path on it
+pub fn http_stand_in(path: &str, delay: Duration, respond: impl Fn(&str) -> String + Send + Sync + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}{}", listener.local_addr().unwrap(), path);
+    let respond = Arc::new(respond);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let respond = respond.clone();
+            thread::spawn(move || {
+                let request = read_request(&mut stream);
+                thread::sleep(delay);
+                let _ = stream.write_all(respond(&request).as_bytes());
+            });
+        }
+    });
+    url
+}
+
+//a response with status that says ok
+pub fn http_response(status: &str, extra_headers: &str) -> String {
+    format!("HTTP/1.1 {}\r\n{}Content-Length: 2\r\nConnection: close\r\n\r\nok", status, extra_headers)
+}
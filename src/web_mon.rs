@@ -1,71 +1,440 @@
-use async_std::task;
+use async_lock::Semaphore;
+use async_std::future::timeout;
+use futures::future::join_all;
 use std::{
         time::{
             Duration,
             Instant,
         },
-        sync::{
-            Arc,
-            mpsc::{
-                Sender,
-            }
-        },
+        sync::Arc,
 };
-use crate::configuration::Configuration;
-use surf::http::StatusCode;
+use crate::alert::{Alert, AlertSender, MonitorKind};
+use crate::configuration::{Configuration, UrlConfig};
+use crate::headers::ExtraHeaders;
+use crate::scheduler::Schedule;
+use crate::status::{ResendTimer, State, StateTracker, Transition};
+use surf::{
+    http::{
+        header::{HeaderName, HeaderValue},
+        Method,
+        StatusCode,
+    },
+    url::Url,
+};
+
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, PartialEq)]
+enum UrlStatus {
+    Up(StatusCode),
+    BadStatus(StatusCode),
+    ConnectionError(String),
+    Timeout(Duration),
+    FailedAssertion(String), //the name of the assertion and what was wrong
+}
+
+impl UrlStatus {
+    fn is_good(&self) -> bool {
+        matches!(self, UrlStatus::Up(_))
+    }
+
+    fn describe(&self, url: &str) -> String {
+        match self {
+            UrlStatus::Up(s) | UrlStatus::BadStatus(s) => format!("http status for {} is {}",url, s),
+            UrlStatus::ConnectionError(e) => format!("Error connecting to {}. {}",url, e),
+            UrlStatus::Timeout(t) => format!("Timed out after {} seconds waiting for {}",t.as_secs_f64(), url),
+            UrlStatus::FailedAssertion(e) => format!("Assertion failed for {}. {}",url, e),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Latency {
+    first_byte: Duration, //until the headers of the final response arrived
+    total: Duration, //until the whole body had been read
+}
+
+//the outcome of one check of a url
+#[derive(Debug, PartialEq)]
+struct UrlCheck {
+    status: UrlStatus,
+    latency: Option<Latency>, //None when there was no response to time
+    state: State, //critical when the url is down, otherwise decided by the latency thresholds
+}
+
+impl UrlCheck {
+    fn new(url: &UrlConfig, status: UrlStatus, latency: Option<Latency>) -> UrlCheck {
+        let state = match latency {
+            _ if !status.is_good() => State::Critical,
+            None => State::Ok,
+            Some(l) => url.latency.state(l.total.as_millis() as f64),
+        };
+        UrlCheck { status, latency, state }
+    }
+
+    fn describe(&self, url: &str) -> String {
+        let mut description = self.status.describe(url);
+        if let Some(l) = self.latency {
+            description += &format!(" in {} ms (first byte after {} ms)", l.total.as_millis(), l.first_byte.as_millis());
+        }
+        if self.status.is_good() && self.state != State::Ok {
+            description += &format!(". Response time is {}", self.state);
+        }
+        description
+    }
+}
+
+//sends the request described by url and follows redirects if it's configured to. A 303 turns
+//the request into a GET without a body, any other redirect is sent the same way again
+async fn fetch(url: &UrlConfig) -> Result<surf::Response,String> {
+    let mut method = Method::from_bytes(url.method.as_bytes()).map_err(|e| e.to_string())?;
+    let mut target = Url::parse(&url.url).map_err(|e| e.to_string())?;
+    let mut body = url.body.clone();
+    //names and values were checked when the configuration was read
+    let headers: Vec<(HeaderName, HeaderValue)> = url.headers.iter()
+        .filter_map(|(n, v)| Some((HeaderName::from_bytes(n.as_bytes()).ok()?, HeaderValue::from_str(v).ok()?)))
+        .collect();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let mut request = surf::Request::new(method.clone(), target.clone());
+        if let Some(b) = &body {
+            request = request.body_string(b.clone());
+        }
+        let response = request.middleware(ExtraHeaders(headers.clone())).await.map_err(|e| e.to_string())?;
+
+        let location = match response.header("Location") {
+            Some(l) if url.follow_redirects && response.status().is_redirection() => l,
+            _ => return Ok(response)
+        };
+        target = target.join(location).map_err(|e| format!("bad redirect to {}. {}", location, e))?;
+        if response.status() == StatusCode::SEE_OTHER {
+            method = Method::GET;
+            body = None;
+        }
+    }
+    Err(format!("gave up after {} redirects", MAX_REDIRECTS))
+}
+
+//sends the request and checks the status and body of the response
+async fn request(url: &UrlConfig) -> UrlCheck {
+    let start = Instant::now();
+    let mut response = match fetch(url).await {
+        Err(e) => return UrlCheck::new(url, UrlStatus::ConnectionError(e), None),
+        Ok(r) => r
+    };
+    let first_byte = start.elapsed();
+    let latency = |total| Some(Latency { first_byte, total });
+
+    //don't read a body the server already told us is too big
+    let too_big = |size: u64| url.max_body_bytes
+        .filter(|max| size > *max)
+        .map(|max| UrlStatus::FailedAssertion(format!("max_body_bytes {}: body is {} bytes", max, size)));
+    let content_length = response.header("Content-Length").and_then(|l| l.parse().ok());
+    if let Some(status) = content_length.and_then(too_big) {
+        return UrlCheck::new(url, status, latency(start.elapsed()));
+    }
+
+    //the body is always read so that the total time includes it
+    let body = match response.body_bytes().await {
+        Err(e) => return UrlCheck::new(url, UrlStatus::ConnectionError(format!("Could not read the body. {}", e)), None),
+        Ok(b) => b
+    };
+    let latency = latency(start.elapsed());
+
+    if !url.accepts(response.status().as_u16()) {
+        return UrlCheck::new(url, UrlStatus::BadStatus(response.status()), latency);
+    }
+    if let Some(status) = too_big(body.len() as u64) {
+        return UrlCheck::new(url, status, latency);
+    }
+    let body = String::from_utf8_lossy(&body);
+    for assertion in url.assertions.iter() {
+        if let Err(e) = assertion.check(&body) {
+            return UrlCheck::new(url, UrlStatus::FailedAssertion(format!("{}: {}", assertion, e)), latency);
+        }
+    }
+    UrlCheck::new(url, UrlStatus::Up(response.status()), latency)
+}
+
+//fetches the url once. in_flight caps how many requests all the url checks together can have
+//waiting on a response, and time spent waiting for a slot doesn't count towards the timeout
+async fn check_url(url: &UrlConfig, in_flight: &Semaphore) -> UrlCheck {
+    let _slot = in_flight.acquire().await;
+    let time_limit = Duration::from_secs(url.timeout);
+    let check = timeout(time_limit, request(url)).await
+        .unwrap_or_else(|_| UrlCheck::new(url, UrlStatus::Timeout(time_limit), None));
+
+    match check.state {
+        State::Ok => log::info!("{}",check.describe(&url.url)),
+        State::Warn => log::warn!("{}",check.describe(&url.url)),
+        State::Critical => log::error!("{}",check.describe(&url.url)),
+    }
+    check
+}
+
+//checks the url once and sends an alert when its state changes or it's time to resend it, so a
+//url that's down is reported once and again when it's back
+async fn check_and_report(url: &UrlConfig, in_flight: &Semaphore, tracker: &mut StateTracker, resend: bool, alert_tx: Option<&AlertSender>) -> State {
+    let check = check_url(url, in_flight).await;
+    let description = check.describe(&url.url);
+    let previous = tracker.current();
+    let duration = tracker.in_state_for();
+    let msg = match tracker.update(check.state) {
+        Some(Transition { from: Some(from), .. }) => format!("[web] {} is {} (was {}): {}", url.url, check.state, from, description),
+        Some(_) => format!("[web] {} is {}: {}", url.url, check.state, description),
+        None if resend => format!("[web] {} is still {}: {}", url.url, check.state, description),
+        None => return check.state
+    };
+
+    let mut alert = Alert::new(MonitorKind::Web, &url.url, msg).with_state(check.state, previous).with_duration(duration);
+    if let Some(l) = check.latency {
+        alert = alert.with_value(l.total.as_millis()); //milliseconds
+    }
+    if !url.latency.is_empty() {
+        alert = alert.with_threshold(&url.latency);
+    }
+    if let Some(tx) = alert_tx {
+        if let Err(e) = tx.send(alert) {
+            log::error!("Could not queue the alert:{}",e);
+        }
+    }
+    check.state
+}
 
-pub async fn web_mon_start(config: Arc<Configuration>, slack_tx: Sender<String>) -> Result<(),String> {
+//checks one url on its own interval forever
+async fn watch_url(config: &Configuration, url: &UrlConfig, in_flight: &Semaphore, alert_tx: AlertSender) {
+    let mut schedule = Schedule::every_seconds(url.interval);
+    //this is to send the status every resend_status_minutes minutes even if
+    //the url is good
+    let mut resend_timer = ResendTimer::new(config.resend_status_minutes);
+    let mut tracker = StateTracker::default();
+    let alert_tx = if config.is_notify_configured() { Some(&alert_tx) } else { None };
+
+    loop {
+        schedule.tick().await;
+        let resend = resend_timer.due();
+        if resend {
+            log::info!("web_mon is resending the status of {}",url.url);
+        }
+        check_and_report(url, in_flight, &mut tracker, resend, alert_tx).await;
+    }
+}
+
+pub async fn web_mon_start(config: Arc<Configuration>, alert_tx: AlertSender) -> Result<(),String> {
     if config.monitor_urls.is_none() {
         log::warn!("web monitoring is not configured");
         return Err("Web monitoring is not configured".to_string())
     }
 
     let monitor_urls = config.monitor_urls.as_ref().unwrap();
-    //this is to send the config to slack every config_resend_status_minutes minutes even if 
-    //the configuration is good
-    let mut now = Instant::now();
+    let in_flight = Semaphore::new(config.max_concurrent_requests);
+    let watches = monitor_urls.iter().map(|url| watch_url(&config, url, &in_flight, alert_tx.clone()));
+    join_all(watches).await;
+    Err("web_mon is exiting. This should never happen".to_string())
+}
 
-    loop {
-        //let res = surf::get("https://www.yachtlogger.com/heartbeat").await;
-
-        //set this flag to true if something goes wrong with one of the urls. Only send
-        //messages to slack if it is true
-        let mut bad_result = false;
-        let mut results: Vec<String> = Vec::new();
-        for url in monitor_urls.iter() {
-            let res = surf::get(url).await;
-            match res {
-                Err(e) => {
-                    bad_result = true;
-                    let s = format!("Error connecting to {}. {}",url, e.to_string());
-                    log::error!("{}",s);
-                    results.push(s);
-                },
-                Ok(result) => {
-                    let s = format!("http status for {} is {}",url, result.status());
-                    log::info!("{}",s);
-                    if result.status() != StatusCode::OK { bad_result = true;}
-                    results.push(s)
-                }
-            }
-        }
-        
-        if config.is_slack_configured() {
-            let do_slack = now.elapsed().as_secs() >= config.resend_status_minutes*60;
-            if do_slack {
-                log::info!("web_mon is resending its status");
-                now = Instant::now();
+#[cfg(test)]
+mod tests {
+    use super::{check_and_report, check_url, UrlStatus};
+    use crate::alert::{alert_channel, QueueFull};
+    use crate::status::{State, StateTracker, Thresholds};
+    use crate::assertions::Assertion;
+    use crate::configuration::UrlConfig;
+    use async_lock::Semaphore;
+    use crate::test_support::{http_response, http_stand_in};
+    use futures::future::join;
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    fn stand_in(delay: Duration, respond: impl Fn(&str) -> String + Send + Sync + 'static) -> String {
+        http_stand_in("/heartbeat", delay, respond)
+    }
+
+    fn ok(_request: &str) -> String {
+        http_response("200 OK", "")
+    }
+
+    fn url_config(url: &str, timeout: u64) -> UrlConfig {
+        let mut u = UrlConfig::new(url, 60);
+        u.timeout = timeout;
+        u
+    }
+
+    #[async_std::test]
+    async fn statuses_are_told_apart() {
+        let in_flight = Semaphore::new(10);
+        let good = stand_in(Duration::from_millis(0), ok);
+        let bad = stand_in(Duration::from_millis(0), |_| http_response("503 Service Unavailable", ""));
+        let hung = stand_in(Duration::from_secs(5), ok);
+
+        assert!(check_url(&url_config(&good, 5), &in_flight).await.status.is_good());
+        assert!(matches!(check_url(&url_config(&bad, 5), &in_flight).await.status, UrlStatus::BadStatus(_)));
+        let start = Instant::now();
+        assert_eq!(check_url(&url_config(&hung, 1), &in_flight).await.status, UrlStatus::Timeout(Duration::from_secs(1)));
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert!(matches!(check_url(&url_config("http://127.0.0.1:1/", 5), &in_flight).await.status, UrlStatus::ConnectionError(_)));
+        assert_eq!(UrlStatus::Timeout(Duration::from_secs(30)).describe("http://x"), "Timed out after 30 seconds waiting for http://x");
+    }
+
+    #[async_std::test]
+    async fn failures_and_recoveries_are_sent_once() {
+        let in_flight = Semaphore::new(10);
+        let status = Arc::new(Mutex::new("503 Service Unavailable"));
+        let answer = status.clone();
+        let url = url_config(&stand_in(Duration::from_millis(0), move |_| http_response(*answer.lock().unwrap(), "")), 5);
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut tracker = StateTracker::default();
+
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, false, Some(&tx)).await, State::Critical);
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, false, Some(&tx)).await, State::Critical);
+        let sent: Vec<_> = rx.try_iter().collect();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].message.starts_with(&format!("[web] {} is CRITICAL: http status for", url.url)), "{}", sent[0].message);
+
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, true, Some(&tx)).await, State::Critical);
+        assert!(rx.try_recv().unwrap().message.starts_with(&format!("[web] {} is still CRITICAL", url.url)));
+
+        *status.lock().unwrap() = "200 OK";
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, false, Some(&tx)).await, State::Ok);
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, false, Some(&tx)).await, State::Ok);
+        let sent: Vec<_> = rx.try_iter().collect();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].message.starts_with(&format!("[web] {} is OK (was CRITICAL): http status for", url.url)), "{}", sent[0].message);
+        assert_eq!((sent[0].state, sent[0].previous_state), (Some(State::Ok), Some(State::Critical)));
+    }
+
+    #[async_std::test]
+    async fn slow_responses_recover() {
+        let in_flight = Semaphore::new(10);
+        let delay = Arc::new(Mutex::new(Duration::from_millis(300)));
+        let wait = delay.clone();
+        let mut url = url_config(&stand_in(Duration::from_millis(0), move |_| {
+            std::thread::sleep(*wait.lock().unwrap());
+            ok("")
+        }), 5);
+        url.latency = Thresholds { warn_above: Some(150.0), ..Thresholds::default() };
+        let (tx, rx) = alert_channel(100, QueueFull::DropOldest);
+        let mut tracker = StateTracker::default();
+
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, false, Some(&tx)).await, State::Warn);
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, false, Some(&tx)).await, State::Warn);
+        let sent: Vec<_> = rx.try_iter().collect();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].message.ends_with("Response time is WARN"), "{}", sent[0].message);
+
+        *delay.lock().unwrap() = Duration::from_millis(0);
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, false, Some(&tx)).await, State::Ok);
+        assert_eq!(check_and_report(&url, &in_flight, &mut tracker, false, Some(&tx)).await, State::Ok);
+        let sent: Vec<_> = rx.try_iter().collect();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].message.starts_with(&format!("[web] {} is OK (was WARN): http status for", url.url)), "{}", sent[0].message);
+        assert_eq!(sent[0].threshold.as_deref(), Some("warn_above 150"));
+    }
+
+    #[async_std::test]
+    async fn in_flight_requests_are_capped() {
+        let slow = url_config(&stand_in(Duration::from_millis(300), ok), 5);
+
+        let in_flight = Semaphore::new(2);
+        let start = Instant::now();
+        let (a, b) = join(check_url(&slow, &in_flight), check_url(&slow, &in_flight)).await;
+        assert!(a.status.is_good() && b.status.is_good());
+        assert!(start.elapsed() < Duration::from_millis(550));
+
+        let in_flight = Semaphore::new(1);
+        let start = Instant::now();
+        let (a, b) = join(check_url(&slow, &in_flight), check_url(&slow, &in_flight)).await;
+        assert!(a.status.is_good() && b.status.is_good());
+        assert!(start.elapsed() >= Duration::from_millis(600));
+    }
+
+    #[async_std::test]
+    async fn requests_are_sent_as_configured() {
+        let in_flight = Semaphore::new(10);
+        //only a POST with the right token and body is healthy
+        let api = stand_in(Duration::from_millis(0), |request| {
+            let lower = request.to_lowercase();
+            if request.starts_with("POST /heartbeat") && lower.contains("authorization: bearer abc") && request.contains("{\"ping\":1}") {
+                http_response("204 No Content", "")
+            } else {
+                http_response("401 Unauthorized", "")
             }
+        });
+
+        let mut post = url_config(&api, 5);
+        assert!(matches!(check_url(&post, &in_flight).await.status, UrlStatus::BadStatus(_)));
+        post.method = "POST".to_string();
+        post.headers = vec![("Authorization".to_string(), "Bearer abc".to_string())];
+        post.body = Some("{\"ping\":1}".to_string());
+        assert!(matches!(check_url(&post, &in_flight).await.status, UrlStatus::BadStatus(_)));
+        post.accepted_status = vec![(200, 299)];
+        assert!(check_url(&post, &in_flight).await.status.is_good());
+    }
 
-            if bad_result || do_slack {
-                for res in results.iter() {
-                    if let Err(e) = slack_tx.send(res.to_string()) {
-                        log::error!("Could not send to slack:{}",e.to_string());
-                        continue;
-                    }
-                }
+    #[async_std::test]
+    async fn bodies_are_checked() {
+        let in_flight = Semaphore::new(10);
+        let degraded = stand_in(Duration::from_millis(0), |_| {
+            let body = r#"{"status":"degraded","queue":12}"#;
+            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+        });
+        let assertion = |s: &str| Assertion::from_toml("test", &toml::from_str(s).unwrap()).unwrap();
+
+        let mut url = url_config(&degraded, 5);
+        url.assertions = vec![assertion("contains = \"status\""), assertion("json = \"/queue\"\nbelow = 100")];
+        assert!(check_url(&url, &in_flight).await.status.is_good());
+
+        url.assertions.push(assertion("json = \"/status\"\nequals = \"ok\""));
+        let status = check_url(&url, &in_flight).await.status;
+        assert_eq!(status, UrlStatus::FailedAssertion("json /status equals \"ok\": got \"degraded\"".to_string()));
+        assert_eq!(status.describe("http://x"), "Assertion failed for http://x. json /status equals \"ok\": got \"degraded\"");
+
+        url.assertions.clear();
+        url.max_body_bytes = Some(10);
+        assert_eq!(check_url(&url, &in_flight).await.status, UrlStatus::FailedAssertion("max_body_bytes 10: body is 32 bytes".to_string()));
+    }
+
+    #[async_std::test]
+    async fn slow_responses_are_flagged() {
+        let in_flight = Semaphore::new(10);
+        let mut slow = url_config(&stand_in(Duration::from_millis(300), ok), 5);
+
+        let check = check_url(&slow, &in_flight).await;
+        let latency = check.latency.unwrap();
+        assert!(latency.total >= Duration::from_millis(300) && latency.first_byte <= latency.total);
+        assert_eq!(check.state, State::Ok);
+
+        slow.latency = Thresholds { warn_above: Some(100.0), critical_above: Some(5000.0), ..Thresholds::default() };
+        let check = check_url(&slow, &in_flight).await;
+        assert_eq!(check.state, State::Warn);
+        assert!(check.status.is_good());
+        let description = check.describe("http://x");
+        assert!(description.starts_with("http status for http://x is 200 OK in "), "{}", description);
+        assert!(description.ends_with("ms). Response time is WARN"), "{}", description);
+
+        let check = check_url(&url_config("http://127.0.0.1:1/", 5), &in_flight).await;
+        assert_eq!((check.latency, check.state), (None, State::Critical));
+    }
+
+    #[async_std::test]
+    async fn redirects_are_only_followed_when_asked() {
+        let in_flight = Semaphore::new(10);
+        let moved = stand_in(Duration::from_millis(0), |request| {
+            if request.starts_with("GET /heartbeat") {
+                http_response("301 Moved Permanently", "Location: /new-heartbeat\r\n")
+            } else {
+                ok(request)
             }
-        }
-        task::sleep(Duration::from_secs(config.sleep_seconds)).await;
+        });
+
+        let mut url = url_config(&moved, 5);
+        assert!(matches!(check_url(&url, &in_flight).await.status, UrlStatus::BadStatus(s) if s.as_u16() == 301));
+        url.accepted_status = vec![(301, 301)];
+        assert!(check_url(&url, &in_flight).await.status.is_good());
+        url.accepted_status = vec![(200, 200)];
+        url.follow_redirects = true;
+        assert!(check_url(&url, &in_flight).await.status.is_good());
     }
 }
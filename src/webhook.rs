@@ -0,0 +1,227 @@
+use async_std::future::timeout;
+use futures::future::BoxFuture;
+use std::time::Duration;
+use crate::alert::{describe_duration, Alert};
+use crate::configuration::WebhookConfig;
+use crate::headers::ExtraHeaders;
+use crate::notifier::{hostname, Notifier};
+use surf::{
+    http::{
+        header::{HeaderName, HeaderValue, CONTENT_TYPE},
+        Method,
+    },
+    url::Url,
+};
+
+//what a template can fill in
+const VARIABLES: [&str; 15] = [
+    "check_id", "monitor", "check", "headline", "state", "previous_state", "severity", "value",
+    "threshold", "duration", "duration_seconds", "message", "timestamp", "host", "color",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+//text with {{variable}} placeholders that are filled in from an alert
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template,String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..].find("}}").ok_or(format!("{{{{ isn't closed with }}}} in {}", template))? + start;
+            let name = rest[start + 2..end].trim();
+            if !VARIABLES.contains(&name) {
+                return Err(format!("{{{{{}}}}} is not a variable. Templates can use {}", name, VARIABLES.join(", ")));
+            }
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            parts.push(Part::Variable(name.to_string()));
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    //fills in the variables from alert. escape is applied to every value so that a message with
+    //quotes in it can't break a json body. Values the alert doesn't have are left empty
+    pub fn render(&self, alert: &Alert, host: &str, escape: fn(&str) -> String) -> String {
+        self.parts.iter().map(|part| match part {
+            Part::Text(text) => text.clone(),
+            Part::Variable(name) => escape(&variable(name, alert, host)),
+        }).collect()
+    }
+}
+
+fn variable(name: &str, alert: &Alert, host: &str) -> String {
+    match name {
+        "check_id" => alert.check_id.clone(),
+        "monitor" => alert.monitor.to_string(),
+        "check" => alert.check.clone(),
+        "headline" => alert.headline(),
+        "state" => alert.state.map(|s| s.to_string()).unwrap_or_default(),
+        "previous_state" => alert.previous_state.map(|s| s.to_string()).unwrap_or_default(),
+        "severity" => alert.severity.to_string(),
+        "value" => alert.value.clone().unwrap_or_default(),
+        "threshold" => alert.threshold.clone().unwrap_or_default(),
+        "duration" => alert.duration.map(describe_duration).unwrap_or_default(),
+        "duration_seconds" => alert.duration.map(|d| d.as_secs().to_string()).unwrap_or_default(),
+        "message" => alert.message.clone(),
+        "timestamp" => alert.timestamp.to_rfc3339(),
+        "host" => host.to_string(),
+        "color" => alert.severity.color().to_string(),
+        _ => String::new(),
+    }
+}
+
+//what goes between the quotes of a json string
+fn json_escape(text: &str) -> String {
+    let quoted = serde_json::Value::String(text.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn no_escape(text: &str) -> String {
+    text.to_string()
+}
+
+//sends every alert to a url with a body made from a template, so that any service that takes
+//json, or anything else, can be notified
+pub struct WebhookNotifier {
+    name: String,
+    config: WebhookConfig,
+    host: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(name: &str, config: &WebhookConfig) -> WebhookNotifier {
+        WebhookNotifier {
+            name: name.to_string(),
+            config: config.clone(),
+            host: hostname(),
+        }
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(),String> {
+        let escape = if self.config.content_type.contains("json") { json_escape } else { no_escape };
+        let body = self.config.body.render(alert, &self.host, escape);
+        let mut headers = vec![(CONTENT_TYPE, HeaderValue::from_str(&self.config.content_type).map_err(|e| e.to_string())?)];
+        for (name, value) in self.config.headers.iter() {
+            let value = value.render(alert, &self.host, no_escape);
+            headers.push((
+                HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?,
+                HeaderValue::from_str(&value).map_err(|_| format!("header {} would have an invalid value: {}", name, value))?,
+            ));
+        }
+        //the method and the url were checked when the configuration was read
+        let method = Method::from_bytes(self.config.method.as_bytes()).map_err(|e| e.to_string())?;
+        let url = Url::parse(&self.config.url).map_err(|e| e.to_string())?;
+
+        log::debug!("Trying to send this to {}:{}", self.config.url, body);
+        let request = surf::Request::new(method, url).body_string(body).middleware(ExtraHeaders(headers));
+        let response = match timeout(Duration::from_secs(self.config.timeout), request).await {
+            Err(_) => return Err(format!("{} didn't answer within {} seconds", self.config.url, self.config.timeout)),
+            Ok(Err(e)) => return Err(format!("Could not connect to {}: {}", self.config.url, e)),
+            Ok(Ok(r)) => r
+        };
+        if !response.status().is_success() {
+            return Err(format!("{} returned: {}", self.config.url, response.status()));
+        }
+        Ok(())
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<(),String>> {
+        Box::pin(self.send(alert))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Template, WebhookNotifier};
+    use crate::alert::{Alert, MonitorKind};
+    use crate::configuration::WebhookConfig;
+    use crate::notifier::Notifier;
+    use crate::status::State;
+    use crate::test_support::{http_response, http_stand_in, request_body};
+    use std::{
+        sync::{mpsc, Mutex},
+        time::Duration,
+    };
+
+    //a stand in for the receiving service that answers every request with status and hands over
+    //the head and the body of what it got
+    fn webhook_server(status: &'static str) -> (String, mpsc::Receiver<(String, String)>) {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let url = http_stand_in("/hooks/yam", Duration::from_millis(0), move |request| {
+            let head = request.split("\r\n\r\n").next().unwrap_or_default();
+            let _ = tx.lock().unwrap().send((head.to_string(), request_body(request)));
+            http_response(status, "")
+        });
+        (url, rx)
+    }
+
+    fn alert() -> Alert {
+        Alert::new(MonitorKind::Tcp, "redis", "[tcp] redis is CRITICAL: \"Connection refused\"".to_string())
+            .with_state(State::Critical, Some(State::Ok))
+            .with_duration(Some(Duration::from_secs(125)))
+    }
+
+    #[test]
+    fn templates_fill_in_the_alert() {
+        let t = Template::parse("{{ headline }} on {{host}}, was {{previous_state}} for {{duration}} ({{duration_seconds}}s). {{value}}").unwrap();
+        assert_eq!(t.render(&alert(), "web1", super::no_escape), "redis is CRITICAL on web1, was OK for 2m 5s (125s). ");
+        let t = Template::parse(r#"{"content": "{{message}}"}"#).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&t.render(&alert(), "web1", super::json_escape)).unwrap();
+        assert_eq!(body["content"], "[tcp] redis is CRITICAL: \"Connection refused\"");
+
+        assert!(Template::parse("{{mesage}}").is_err());
+        assert!(Template::parse("{{message").is_err());
+        assert_eq!(Template::parse("no variables").unwrap().render(&alert(), "web1", super::no_escape), "no variables");
+    }
+
+    #[async_std::test]
+    async fn alerts_are_sent_as_configured() {
+        let (url, rx) = webhook_server("204 No Content");
+        let config = WebhookConfig {
+            url,
+            method: "PUT".to_string(),
+            headers: vec![
+                ("Authorization".to_string(), Template::parse("Bearer abc").unwrap()),
+                ("X-Check".to_string(), Template::parse("{{check_id}}").unwrap()),
+            ],
+            body: Template::parse(r#"{"text": "{{message}}", "state": "{{state}}"}"#).unwrap(),
+            content_type: "application/json".to_string(),
+            timeout: 5,
+        };
+        let webhook = WebhookNotifier::new("bot", &config);
+        webhook.notify(&alert()).await.unwrap();
+
+        let (head, body) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(head.starts_with("PUT /hooks/yam HTTP/1.1\r\n"), "{}", head);
+        let head = head.to_lowercase();
+        assert!(head.contains("\r\nauthorization: bearer abc") && head.contains("\r\nx-check: tcp/redis"), "{}", head);
+        assert!(head.contains("\r\ncontent-type: application/json"), "{}", head);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body, serde_json::json!({ "text": "[tcp] redis is CRITICAL: \"Connection refused\"", "state": "CRITICAL" }));
+
+        let (url, _rx) = webhook_server("404 Not Found");
+        let webhook = WebhookNotifier::new("bot", &WebhookConfig { url, ..config });
+        assert!(webhook.notify(&alert()).await.unwrap_err().contains("404"));
+    }
+}